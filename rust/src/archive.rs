@@ -0,0 +1,191 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::ram::MEM_BYTES;
+
+// Extensions ROMs are distributed with, to tell them apart from the readmes and screenshots that
+// come with them in a pack
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "hc8"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Zip,
+    Gzip,
+}
+
+/// A file in an archive.
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+}
+
+/// What kind of archive `path` is, going by its extension.
+pub fn kind(path: &Path) -> Option<Kind> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "zip" => Some(Kind::Zip),
+        "gz" => Some(Kind::Gzip),
+        _ => None,
+    }
+}
+
+pub fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|e| ROM_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// The files in an archive, in the order they're stored. A gzip file holds just the one, named
+/// as it was before it was compressed.
+pub fn entries(path: &Path, kind: Kind) -> Result<Vec<Entry>, String> {
+    match kind {
+        Kind::Zip => {
+            let mut zip = open_zip(path)?;
+            let mut entries = Vec::new();
+            for i in 0..zip.len() {
+                let file = zip.by_index(i).map_err(|e| e.to_string())?;
+                if file.is_file() {
+                    entries.push(Entry {
+                        name: file.name().to_string(),
+                        size: file.size(),
+                    });
+                }
+            }
+            Ok(entries)
+        }
+        Kind::Gzip => {
+            let (name, data) = read_gzip(path)?;
+            Ok(vec![Entry {
+                name,
+                size: data.len() as u64,
+            }])
+        }
+    }
+}
+
+/// The entries worth choosing between: the ROMs, or everything if none look like ROMs.
+pub fn candidates(entries: &[Entry]) -> Vec<&Entry> {
+    let roms: Vec<&Entry> = entries.iter().filter(|e| is_rom_name(&e.name)).collect();
+    if roms.is_empty() {
+        entries.iter().collect()
+    } else {
+        roms
+    }
+}
+
+/// The entry to load when none was chosen: the only file in the archive, or the only ROM
+/// among several files.
+pub fn default_entry(entries: &[Entry]) -> Option<&Entry> {
+    match candidates(entries).as_slice() {
+        [only] => Some(only),
+        _ => None,
+    }
+}
+
+/// Read the entry called `name` out of an archive, or the default entry if no name is given.
+pub fn read_rom(path: &Path, kind: Kind, name: Option<&str>) -> Result<Vec<u8>, String> {
+    let entries = entries(path, kind)?;
+    let name = match name {
+        Some(name) if entries.iter().any(|e| e.name == name) => name,
+        Some(name) => return Err(format!("There's no {} in the archive", name)),
+        None => match default_entry(&entries) {
+            Some(entry) => &entry.name,
+            None if entries.is_empty() => return Err("The archive is empty".to_string()),
+            None => {
+                let names: Vec<&str> = candidates(&entries)
+                    .iter()
+                    .map(|e| e.name.as_str())
+                    .collect();
+                return Err(format!(
+                    "The archive holds several ROMs, so one must be chosen: {}",
+                    names.join(", ")
+                ));
+            }
+        },
+    };
+
+    match kind {
+        Kind::Zip => {
+            let mut zip = open_zip(path)?;
+            let file = zip.by_name(name).map_err(|e| e.to_string())?;
+            read_limited(file)
+        }
+        Kind::Gzip => Ok(read_gzip(path)?.1),
+    }
+}
+
+/// A file read out of an archive: its name, and either its contents or why they couldn't be read.
+pub type Contents = (String, Result<Vec<u8>, String>);
+
+/// Read every file out of an archive at once, in the order they're stored.
+pub fn read_all(path: &Path, kind: Kind) -> Result<Vec<Contents>, String> {
+    match kind {
+        Kind::Zip => {
+            let mut zip = open_zip(path)?;
+            let mut files = Vec::new();
+            for i in 0..zip.len() {
+                let file = zip.by_index(i).map_err(|e| e.to_string())?;
+                if file.is_file() {
+                    let name = file.name().to_string();
+                    files.push((name, read_limited(file)));
+                }
+            }
+            Ok(files)
+        }
+        Kind::Gzip => {
+            let (name, data) = read_gzip(path)?;
+            Ok(vec![(name, Ok(data))])
+        }
+    }
+}
+
+/// Read a ROM file, or an entry from an archive as `read_rom` does.
+pub fn read_rom_file(path: &Path, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    match kind(path) {
+        Some(kind) => read_rom(path, kind, entry),
+        None => std::fs::read(path).map_err(|e| e.to_string()),
+    }
+}
+
+fn open_zip(path: &Path) -> Result<ZipArchive<File>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    ZipArchive::new(file).map_err(|e| e.to_string())
+}
+
+// The original file name from the gzip header if it has one, otherwise the archive's own name
+// without the .gz
+fn read_gzip(path: &Path) -> Result<(String, Vec<u8>), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = GzDecoder::new(file);
+    let data = read_limited(&mut decoder)?;
+    let name = decoder
+        .header()
+        .and_then(|h| h.filename())
+        .map(|n| String::from_utf8_lossy(n).into_owned())
+        .or_else(|| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    Ok((name, data))
+}
+
+// Read a file out of an archive, giving up on anything too large to fit in memory rather than
+// decompressing all of it
+fn read_limited(reader: impl Read) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    reader
+        .take(MEM_BYTES as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    if data.len() > MEM_BYTES {
+        return Err(format!(
+            "It's over {} bytes, too large to be a ROM",
+            MEM_BYTES
+        ));
+    }
+    Ok(data)
+}
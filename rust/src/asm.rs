@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::instruction::Instruction;
+use crate::ram::MEM_BYTES;
+use crate::symbols::{Entry, Symbols};
+
+// Deep enough for any sensible project, shallow enough to catch a file including itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// An assembly error, pointing at the source line that caused it.
+#[derive(Debug)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+/// An assembled program and what the debugger needs to know about it.
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub symbols: Symbols,
+}
+
+#[derive(Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+impl Location {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+enum Symbol {
+    Label(usize),
+    // Constants are kept as source text and evaluated when used, so they can refer to labels
+    // that are only defined further down
+    Constant(String),
+}
+
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+struct Item {
+    location: Location,
+    addr: usize,
+    statement: Statement,
+}
+
+enum Operand<'a> {
+    V(u8),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+    Value(&'a str),
+}
+
+/// Assemble a source file into a ROM that is loaded at `base`.
+///
+/// The syntax follows Cowgod's mnemonics (the same ones the disassembler prints), plus:
+/// - `name:` labels, which may share a line with an instruction
+/// - `NAME EQU value` constants
+/// - `DB` and `DW` data, where `DB` also takes "strings"
+/// - `ORG addr` to skip ahead to an address
+/// - `INCLUDE "file"`, relative to the including file
+///
+/// Numbers can be decimal, hex (`0x1F`, `#1F` or `$1F`) or binary (`0b101` or `%101`), and
+/// anywhere a number goes a sum like `sprites+5` works too. Comments start with `;`.
+pub fn assemble_file(path: &Path, base: u16) -> Result<Program, AsmError> {
+    let mut assembler = Assembler {
+        addr: base as usize,
+        symbols: HashMap::new(),
+        items: Vec::new(),
+        debug_info: Symbols::default(),
+    };
+    let top = Location {
+        file: path.display().to_string(),
+        line: 0,
+    };
+    assembler.read(path, &top, 0)?;
+    let rom = assembler.emit(base as usize)?;
+    Ok(Program {
+        rom,
+        symbols: assembler.debug_info,
+    })
+}
+
+struct Assembler {
+    addr: usize,
+    symbols: HashMap<String, Symbol>,
+    items: Vec<Item>,
+    // Labels and source lines by address, for the symbol file
+    debug_info: Symbols,
+}
+
+impl Assembler {
+    /// First pass: collect labels, constants and statements, working out every statement's
+    /// address along the way.
+    fn read(&mut self, path: &Path, from: &Location, depth: usize) -> Result<(), AsmError> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(from.error("Includes are nested too deeply"));
+        }
+        let source = fs::read_to_string(path)
+            .map_err(|e| from.error(format!("Could not read {}: {}", path.display(), e)))?;
+
+        for (n, line) in source.lines().enumerate() {
+            let location = Location {
+                file: path.display().to_string(),
+                line: n + 1,
+            };
+            let mut line = strip_comment(line).trim();
+
+            while let Some((name, rest)) = line.split_once(':') {
+                if !is_identifier(name.trim()) {
+                    break;
+                }
+                self.define(&location, name.trim(), Symbol::Label(self.addr))?;
+                let label = Entry::Label(name.trim().to_string());
+                self.debug_info.add(self.addr as u16, label);
+                line = rest.trim();
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            let (first, rest) = split_word(line);
+            let (second, value) = split_word(rest);
+            if second.eq_ignore_ascii_case("EQU") {
+                self.define(&location, first, Symbol::Constant(value.to_string()))?;
+                continue;
+            }
+
+            let operands = split_operands(rest);
+            let statement = match first.to_ascii_uppercase().as_str() {
+                "INCLUDE" => {
+                    let [file] = operands.as_slice() else {
+                        return Err(location.error("INCLUDE takes one file name"));
+                    };
+                    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                    let included: PathBuf = dir.join(unquote(file));
+                    self.read(&included, &location, depth + 1)?;
+                    continue;
+                }
+                "ORG" => {
+                    let [addr] = operands.as_slice() else {
+                        return Err(location.error("ORG takes one address"));
+                    };
+                    let addr = self.evaluate(&location, addr, 0)?;
+                    if addr < self.addr as i64 {
+                        return Err(location.error("ORG can't move backwards"));
+                    }
+                    self.addr = addr as usize;
+                    continue;
+                }
+                "DB" => Statement::Bytes(operands),
+                "DW" => Statement::Words(operands),
+                mnemonic => Statement::Instruction {
+                    mnemonic: mnemonic.to_string(),
+                    operands,
+                },
+            };
+
+            let size = match &statement {
+                Statement::Instruction { .. } => 2,
+                Statement::Bytes(values) => values
+                    .iter()
+                    .map(|v| match as_string(v) {
+                        Some(s) => s.len(),
+                        None => 1,
+                    })
+                    .sum(),
+                Statement::Words(values) => 2 * values.len(),
+            };
+            if let Statement::Instruction { .. } = statement {
+                let source = Entry::Source {
+                    file: location.file.clone(),
+                    line: location.line,
+                };
+                self.debug_info.add(self.addr as u16, source);
+            }
+            self.items.push(Item {
+                location,
+                addr: self.addr,
+                statement,
+            });
+            self.addr += size;
+            if self.addr > MEM_BYTES {
+                return Err(self.items.last().unwrap().location.error(format!(
+                    "Program doesn't fit in memory (it would end at {:#X})",
+                    self.addr
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, location: &Location, name: &str, symbol: Symbol) -> Result<(), AsmError> {
+        if !is_identifier(name) || matches!(operand(name), Operand::V(_)) {
+            return Err(location.error(format!("`{}` can't be used as a name", name)));
+        }
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(location.error(format!("`{}` is already defined", name)));
+        }
+        Ok(())
+    }
+
+    /// Second pass: now that every symbol is known, produce the ROM's bytes.
+    fn emit(&self, base: usize) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for item in &self.items {
+            let loc = &item.location;
+            let bytes = match &item.statement {
+                Statement::Instruction { mnemonic, operands } => {
+                    let instruction = self.instruction(loc, mnemonic, operands)?;
+                    instruction.encode().to_be_bytes().to_vec()
+                }
+                Statement::Bytes(values) => {
+                    let mut bytes = Vec::new();
+                    for v in values {
+                        match as_string(v) {
+                            Some(s) => bytes.extend_from_slice(s.as_bytes()),
+                            None => bytes.push(self.value(loc, v, 0xFF, "byte")? as u8),
+                        }
+                    }
+                    bytes
+                }
+                Statement::Words(values) => {
+                    let mut bytes = Vec::new();
+                    for v in values {
+                        let word = self.value(loc, v, 0xFFFF, "word")? as u16;
+                        bytes.extend_from_slice(&word.to_be_bytes());
+                    }
+                    bytes
+                }
+            };
+
+            let offset = item.addr - base;
+            if rom.len() < offset {
+                rom.resize(offset, 0);
+            }
+            rom.extend_from_slice(&bytes);
+        }
+        Ok(rom)
+    }
+
+    fn instruction(
+        &self,
+        loc: &Location,
+        mnemonic: &str,
+        operands: &[String],
+    ) -> Result<Instruction, AsmError> {
+        use Operand::*;
+
+        let ops: Vec<Operand> = operands.iter().map(|o| operand(o)).collect();
+        let addr = |e: &str| self.value(loc, e, 0xFFF, "address").map(|v| v as u16);
+        let byte = |e: &str| self.value(loc, e, 0xFF, "byte").map(|v| v as u8);
+        let nibble = |e: &str| self.value(loc, e, 0xF, "nibble").map(|v| v as u8);
+
+        let instruction = match (mnemonic, ops.as_slice()) {
+            ("CLS", []) => Instruction::ClearScreen,
+            ("RET", []) => Instruction::Return,
+            ("SYS", [Value(a)]) => Instruction::MachineCall { nnn: addr(a)? },
+            ("JP", [Value(a)]) => Instruction::Jump { nnn: addr(a)? },
+            ("JP", [V(0), Value(a)]) => Instruction::JumpPlusV0 { nnn: addr(a)? },
+            ("CALL", [Value(a)]) => Instruction::Call { nnn: addr(a)? },
+            ("SE", [V(x), V(y)]) => Instruction::SkipIfEqReg { x: *x, y: *y },
+            ("SE", [V(x), Value(b)]) => Instruction::SkipIfEq {
+                x: *x,
+                nn: byte(b)?,
+            },
+            ("SNE", [V(x), V(y)]) => Instruction::SkipIfNotEqReg { x: *x, y: *y },
+            ("SNE", [V(x), Value(b)]) => Instruction::SkipIfNotEq {
+                x: *x,
+                nn: byte(b)?,
+            },
+            ("LD", [V(x), V(y)]) => Instruction::SetVxToVy { x: *x, y: *y },
+            ("LD", [V(x), DelayTimer]) => Instruction::GetDelay { x: *x },
+            ("LD", [V(x), Key]) => Instruction::GetKey { x: *x },
+            ("LD", [V(x), IndirectI]) => Instruction::LoadMemory { x: *x },
+            ("LD", [V(x), Value(b)]) => Instruction::SetRegister {
+                x: *x,
+                nn: byte(b)?,
+            },
+            ("LD", [I, Value(a)]) => Instruction::SetIndex { nnn: addr(a)? },
+            ("LD", [DelayTimer, V(x)]) => Instruction::SetDelay { x: *x },
+            ("LD", [SoundTimer, V(x)]) => Instruction::SetSound { x: *x },
+            ("LD", [Font, V(x)]) => Instruction::FontCharacter { x: *x },
+            ("LD", [Bcd, V(x)]) => Instruction::BinaryCodedDecimal { x: *x },
+            ("LD", [IndirectI, V(x)]) => Instruction::StoreMemory { x: *x },
+            ("ADD", [V(x), V(y)]) => Instruction::AddWithCarry { x: *x, y: *y },
+            ("ADD", [V(x), Value(b)]) => Instruction::Add {
+                x: *x,
+                nn: byte(b)?,
+            },
+            ("ADD", [I, V(x)]) => Instruction::AddToIndex { x: *x },
+            ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [V(x), V(y)]) => Instruction::SubtractVyFromVx { x: *x, y: *y },
+            ("SUBN", [V(x), V(y)]) => Instruction::SubtractVxFromVy { x: *x, y: *y },
+            // With no VY, shift VX in place whichever way the interpreter treats VY
+            ("SHR", [V(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+            ("SHR", [V(x), V(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+            ("SHL", [V(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+            ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+            ("RND", [V(x), Value(b)]) => Instruction::Random {
+                x: *x,
+                nn: byte(b)?,
+            },
+            ("DRW", [V(x), V(y), Value(n)]) => Instruction::Draw {
+                x: *x,
+                y: *y,
+                n: nibble(n)?,
+            },
+            ("SKP", [V(x)]) => Instruction::SkipIfPressed { x: *x },
+            ("SKNP", [V(x)]) => Instruction::SkipIfNotPressed { x: *x },
+            _ => {
+                return Err(loc.error(format!(
+                    "Invalid instruction `{} {}`",
+                    mnemonic,
+                    operands.join(", ")
+                )))
+            }
+        };
+        Ok(instruction)
+    }
+
+    /// Evaluate `expr` and check it fits in `max`.
+    fn value(&self, loc: &Location, expr: &str, max: i64, what: &str) -> Result<i64, AsmError> {
+        let v = self.evaluate(loc, expr, 0)?;
+        if !(0..=max).contains(&v) {
+            return Err(loc.error(format!("{} ({}) doesn't fit in a {}", expr, v, what)));
+        }
+        Ok(v)
+    }
+
+    /// Evaluate a sum or difference of numbers and symbols.
+    fn evaluate(&self, loc: &Location, expr: &str, depth: usize) -> Result<i64, AsmError> {
+        if depth > self.symbols.len() {
+            return Err(loc.error(format!("`{}` is defined in terms of itself", expr)));
+        }
+
+        let mut total = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+        for c in expr.chars().chain(std::iter::once('+')) {
+            if c != '+' && c != '-' {
+                term.push(c);
+                continue;
+            }
+            let t = term.trim();
+            if t.is_empty() {
+                if c == '-' {
+                    sign = -sign;
+                }
+                continue;
+            }
+            let v = match parse_number(t) {
+                Some(v) => v,
+                None => match self.symbols.get(t) {
+                    Some(Symbol::Label(addr)) => *addr as i64,
+                    Some(Symbol::Constant(value)) => self.evaluate(loc, value, depth + 1)?,
+                    None if is_identifier(t) => {
+                        return Err(loc.error(format!("Undefined symbol `{}`", t)))
+                    }
+                    None => return Err(loc.error(format!("Invalid number `{}`", t))),
+                },
+            };
+            total += sign * v;
+            sign = if c == '-' { -1 } else { 1 };
+            term.clear();
+        }
+        Ok(total)
+    }
+}
+
+fn operand(s: &str) -> Operand<'_> {
+    let upper = s.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "B" => Operand::Bcd,
+        _ => match upper.strip_prefix('V').map(|n| u8::from_str_radix(n, 16)) {
+            Some(Ok(x)) if upper.len() == 2 => Operand::V(x),
+            _ => Operand::Value(s),
+        },
+    }
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let lower = s.to_ascii_lowercase();
+    if let Some(hex) = lower
+        .strip_prefix("0x")
+        .or_else(|| lower.strip_prefix('#'))
+        .or_else(|| lower.strip_prefix('$'))
+    {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Drop a trailing `;` comment, leaving semicolons inside strings alone.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (s, ""),
+    }
+}
+
+/// Split comma-separated operands, leaving commas inside strings alone.
+fn split_operands(s: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+    for c in s.chars() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                current.push(c);
+            }
+            ',' if !in_string => operands.push(std::mem::take(&mut current).trim().to_string()),
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+fn as_string(s: &str) -> Option<&str> {
+    s.strip_prefix('"')?.strip_suffix('"')
+}
+
+fn unquote(s: &str) -> &str {
+    as_string(s).unwrap_or(s)
+}
@@ -0,0 +1,80 @@
+use crate::instruction::Instruction;
+use crate::ram::{Address, RAM};
+use crate::stack::Stack;
+use crate::symbols::Symbols;
+
+// Recursion is reported once the stack is this close to full, early enough to still be
+// inspected before it overflows
+const RECURSION_HEADROOM: usize = 4;
+// How many frames must be calls into the same subroutine to count as runaway recursion
+const RECURSION_REPEATS: usize = 3;
+
+/// A subroutine call that hasn't returned yet, reconstructed from its return address.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub return_addr: u16,
+    pub call_site: u16,
+    // The CALL's target, unless the code at the call site has since changed
+    pub callee: Option<u16>,
+}
+
+/// The stack's frames, innermost first.
+pub fn frames(stack: &Stack, ram: &RAM) -> Vec<Frame> {
+    stack
+        .iter()
+        .rev()
+        .map(|&return_addr| {
+            let call_site = return_addr.wrapping_sub(2);
+            let opcode = ram.read_word(Address(call_site)).ok();
+            let callee = match opcode.and_then(Instruction::decode) {
+                Some(Instruction::Call { nnn }) => Some(nnn),
+                _ => None,
+            };
+            Frame {
+                return_addr,
+                call_site,
+                callee,
+            }
+        })
+        .collect()
+}
+
+/// Format a backtrace from `pc` down through the stack, one line per frame, like:
+/// ```text
+/// #0  0x2A6 <draw_player+0x4>
+/// #1  0x23C <main+0x3A>, after CALL 0x2A2 <draw_player> at 0x23A <main+0x38>
+/// ```
+pub fn format(pc: u16, frames: &[Frame], symbols: &Symbols) -> Vec<String> {
+    let mut lines = vec![format!("#0  {}", symbols.describe(pc))];
+    for (n, frame) in frames.iter().enumerate() {
+        let callee = match frame.callee {
+            Some(addr) => symbols.describe(addr),
+            None => "?".to_string(),
+        };
+        lines.push(format!(
+            "#{:<2} {}, after CALL {} at {}",
+            n + 1,
+            symbols.describe(frame.return_addr),
+            callee,
+            symbols.describe(frame.call_site)
+        ));
+    }
+    lines
+}
+
+/// If the stack has just filled up to the warning depth with calls into one subroutine, return
+/// that subroutine's address. Only the exact depth is checked, so a runaway is reported once
+/// rather than on every call after. The innermost call is what's looked for further down the
+/// stack, which finds mutual recursion as well as direct.
+pub fn runaway_recursion(stack: &Stack, ram: &RAM) -> Option<u16> {
+    if stack.depth() + RECURSION_HEADROOM != stack.capacity() {
+        return None;
+    }
+    let frames = frames(stack, ram);
+    let innermost = frames.first()?.callee?;
+    let repeats = frames
+        .iter()
+        .filter(|f| f.callee == Some(innermost))
+        .count();
+    (repeats >= RECURSION_REPEATS).then_some(innermost)
+}
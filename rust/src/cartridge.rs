@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::asm::Program;
+use crate::octo;
+use crate::palette::{self, Palette};
+use crate::quirks::Quirks;
+
+/// A compiled cartridge, with the settings it asks to be run with.
+pub struct Cartridge {
+    pub program: Program,
+    // Instructions per 60Hz frame
+    pub tickrate: Option<usize>,
+    pub quirks: Quirks,
+    pub palette: Palette,
+}
+
+pub fn is_cartridge(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("gif"))
+}
+
+/// Decode an Octo cartridge: a GIF image of a cartridge with the program's source and the
+/// options it was written for hidden in the pixels, and compile it.
+///
+/// Each pixel's palette index holds one nybble of the payload in its low four bits, high nybble
+/// first, running through every pixel of every frame in order. The palette repeats each color
+/// of the picture sixteen times, so the data doesn't show. The payload is a 32-bit big-endian
+/// length followed by that many bytes of UTF-8 JSON:
+/// ```text
+/// {"options": {"tickrate": 20, "shiftQuirks": true, "fillColor": "#FFCC00", ...},
+///  "program": ": main ..."}
+/// ```
+pub fn load(path: &Path) -> Result<Cartridge, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let gif_error = |e: gif::DecodingError| format!("Could not decode the GIF: {}", e);
+    let mut decoder = options.read_info(file).map_err(gif_error)?;
+
+    let mut nybbles = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(gif_error)? {
+        nybbles.extend(frame.buffer.iter().map(|index| index & 0xF));
+    }
+    let data: Vec<u8> = nybbles
+        .chunks_exact(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect();
+
+    let not_a_cartridge = || "This GIF doesn't hold an Octo cartridge".to_string();
+    let (size, payload) = data.split_first_chunk::<4>().ok_or_else(not_a_cartridge)?;
+    let payload = payload
+        .get(..u32::from_be_bytes(*size) as usize)
+        .ok_or_else(not_a_cartridge)?;
+    let json: Value = serde_json::from_slice(payload).map_err(|_| not_a_cartridge())?;
+
+    let source = json["program"]
+        .as_str()
+        .ok_or("The cartridge has no program in it")?;
+    let name = path.display().to_string();
+    let program = octo::compile(source, &name).map_err(|e| e.to_string())?;
+    let options = &json["options"];
+    Ok(Cartridge {
+        program,
+        tickrate: options["tickrate"].as_u64().map(|n| n as usize),
+        quirks: quirks(options),
+        palette: palette(options)?,
+    })
+}
+
+// Any colors the options leave out are the default palette's
+fn palette(options: &Value) -> Result<Palette, String> {
+    let default = Palette::default();
+    Ok(Palette {
+        background: color(options, "backgroundColor")?.unwrap_or(default.background),
+        fill: color(options, "fillColor")?.unwrap_or(default.fill),
+        fill2: color(options, "fillColor2")?.unwrap_or(default.fill2),
+        blend: color(options, "blendColor")?.unwrap_or(default.blend),
+    })
+}
+
+// Octo turns a quirk off unless the options say otherwise
+fn quirks(options: &Value) -> Quirks {
+    let quirk = |name: &str| options[name].as_bool().unwrap_or(false);
+    Quirks {
+        shift: quirk("shiftQuirks"),
+        load_store: quirk("loadStoreQuirks"),
+        jump: quirk("jumpQuirks"),
+        logic: quirk("logicQuirks"),
+        clip: quirk("clipQuirks"),
+        vf_order: quirk("vfOrderQuirks"),
+    }
+}
+
+fn color(options: &Value, name: &str) -> Result<Option<palette::Rgb>, String> {
+    options[name].as_str().map(palette::parse_color).transpose()
+}
@@ -0,0 +1,454 @@
+use std::path::Path;
+
+use crate::config::{self, Layer};
+use crate::disasm;
+use crate::palette;
+use crate::quirks::Quirks;
+use crate::ram::{MemoryConfig, FONT_COUNT, PROG_MEM_START};
+use crate::recording::Format;
+use crate::stack::{StackConfig, MAX_STACK_DEPTH};
+use crate::trace::{Column, TraceConfig, ALL_COLUMNS};
+
+pub const USAGE: &str = "\
+usage: chip-8 [debug] [<rom.ch8>] [options]
+       chip-8 disasm <rom.ch8> [--recursive] [--symbols <file>] [--load-address <addr>]
+       chip-8 asm <source.asm|source.8o> [-o <rom.ch8>]
+       chip-8 list <dir|archive>...
+       chip-8 info <rom.ch8> [--entry <name>]
+
+  debug                     run the ROM under the command-line debugger
+  disasm                    print the ROM as assembly, decoding it in one linear sweep, or
+                            by following the code from its entry point with --recursive,
+                            as loaded at 200 unless --load-address says otherwise
+  asm                       assemble source into a ROM, written next to the source with a
+                            .ch8 extension unless -o is given, along with a .sym symbol
+                            file; .8o sources are compiled as Octo
+  list                      list the ROMs in directories and zip or gzip archives, with
+                            their sizes and SHA-1s
+  info                      show what the ROM database knows about a ROM, and guess the
+                            platform and quirks it was written for from its code
+
+The ROM is read from standard input if its filename is -. It can also be a .zip or .gz
+archive, from which the only ROM is loaded, or the one chosen with --entry or at a prompt.
+An Octo cartridge (.gif) runs with the speed, quirks and colors it was saved with.
+
+Settings are read from ~/.config/chip-8/config.toml (or $XDG_CONFIG_HOME/chip-8), then a
+.toml file next to the ROM, then the options below, each taking the place of the last's.
+The ROM database's or a cartridge's settings come before the ROM's file.
+
+In the window, P pauses and resumes, N runs one frame at a time, holding Tab fast-forwards,
+the minus and equals keys halve and double the speed (down to 1/16, and back up to full),
+F3 shows and hides an overlay of speeds and machine state, F5 starts the ROM again (reading
+its file again with Shift or Control held), F9 starts and stops recording, F12 saves a
+screenshot and Escape quits. The speed keys are left alone if the keymap binds them. A ROM
+that crashes is stopped, with the window left open, until it's started again.
+
+options:
+  --entry <name>            the file to load from a zip archive holding several
+  --config <file>           read global settings from file instead of the usual place
+  --print-config            print the settings the ROM would run with as a config file,
+                            and exit
+  --keymap <profile>        the keys to use for the keypad: standard (1234/QWER/ASDF/ZXCV,
+                            the default), legacy, or a profile from a config file
+  --scale <n>               make the window, screenshots and recordings n times the size
+                            of the picture, which is 64x32 unless a filter enlarges it
+                            (default 1)
+  --no-audio                don't ring the terminal bell when the ROM plays a sound
+  --ticks-per-frame <n>     run n instructions per 60Hz frame (by default, as fast as the
+                            frontend goes, unless a cartridge sets a speed)
+  --fast-forward <n>        how many times full speed to run while Tab is held (default 4)
+  --platform <name>         run with the quirks of chip8, schip or xochip, rather than the
+                            ROM database's for the ROM, or the default (SCHIP's, mostly)
+  --quirks <list>           comma-separated quirks to turn on or off on top of those, like
+                            shift=on,clip=off (shift, load-store, jump, logic, clip,
+                            vf-order)
+  --palette <name>          the colors to draw in: mono (the default), green, amber, lcd,
+                            or an XO-CHIP set: octo, hotdog, gray, cga0, cga1
+  --colors <list>           the background and fill colors, like 000000,33FF66, then
+                            optionally XO-CHIP's fill2 and blend colors
+  --filter <name>           upscale the picture in the window, screenshots and recordings
+                            with scale2x, scale3x, epx or scanlines (default none)
+  --hud                     start with the overlay of speeds and machine state shown (F3
+                            shows and hides it; it needs --scale 2 or more to fit)
+  --record-gif <file>       record every frame to an animated GIF, until the window is
+                            closed (F9 records to the current directory instead)
+  --record-png <dir>        record every frame to numbered PNGs in dir instead
+  --frames <n>              record n frames without opening a window, then exit (running
+                            10 instructions a frame unless a speed is set)
+  --watch                   load the ROM again whenever its file changes, such as when it's
+                            been assembled again, and start it afresh
+  --keep-state              with --watch, carry on where the machine was instead, with
+                            only the ROM replaced
+  --decay <frames>          fade erased pixels out over that many frames, which makes
+                            flickering sprites easier to see (0 to 255, default 0)
+  --present <mode>          how each frame is shown, to hide flicker: direct (the VRAM at
+                            the end of the frame, the default), clear (the screen just
+                            before the ROM clears it), blend (this frame ORed with the
+                            last) or lazy (erased pixels show until the next frame)
+  --no-rom-db               don't apply the settings the ROM database has for the ROM
+  --load-address <addr>     where the ROM is loaded and starts running (hex, default 200;
+                            ETI-660 programs start at 600)
+  --symbols <file>          load labels, breakpoints and source lines from a symbol file
+                            (by default, the .sym file next to the ROM if there is one)
+  --snapshot <file>         restore a machine snapshot (such as one saved by a crash
+                            report) after loading the ROM, if one was given
+  --stack-depth <n>         how many return addresses the stack holds (default 16; the
+                            VIP's held 12)
+  --stack-policy <policy>   what a CALL with a full stack or a RET with an empty one does:
+                            error (the default), wrap or ignore
+  --stack-in-ram            keep the stack in emulated RAM where the VIP did (0xEA0-0xECF)
+  --memory-policy <policy>  what reading or writing past the top of memory does: error
+                            (the default) or wrap around to the bottom
+  --protect <mode>          what the ROM writing to read-only memory (the interpreter
+                            area, the font or the VIP's reserved areas) does: error, warn
+                            (the default) or off
+  --font-base <addr>        where the font is loaded (hex, default 0; the C version uses 50)
+  --vip-memory-map          reserve the VIP's stack, variables and display buffer at
+                            0xEA0-0xFFF, leaving the program 0x200-0xE9F
+  --trace <file>            write an execution trace to file
+  --trace-columns <list>    comma-separated trace columns to write
+                            (pc, sym, opcode, mnemonic, regs, i, sp, timers; default all)
+  --trace-start <addr>      only start tracing once the PC reaches addr (hex)
+  --trace-stop <addr>       stop tracing once the PC reaches addr (hex)";
+
+/// What the binary has been asked to do.
+pub enum Command {
+    Run(Options),
+    Debug(Options),
+    Disasm {
+        rom: String,
+        mode: disasm::Mode,
+        symbols: Option<String>,
+        load_address: u16,
+    },
+    Asm {
+        source: String,
+        output: String,
+    },
+    List {
+        paths: Vec<String>,
+    },
+    Info {
+        rom: String,
+        entry: Option<String>,
+    },
+}
+
+pub struct Options {
+    pub rom: Option<String>,
+    pub entry: Option<String>,
+    pub snapshot: Option<String>,
+    pub symbols: Option<String>,
+    pub load_address: u16,
+    // The global config file, if not the usual one
+    pub config: Option<String>,
+    pub print_config: bool,
+    // Settings given on the command line, laid over all others
+    pub overrides: Layer,
+    // Where to record to from the start, and in what format
+    pub record: Option<(String, Format)>,
+    // How many frames to record without a window
+    pub frames: Option<usize>,
+    // Reload the ROM whenever its file changes, keeping the machine's state if asked to
+    pub watch: bool,
+    pub keep_state: bool,
+    pub rom_db: bool,
+    pub stack: StackConfig,
+    pub memory: MemoryConfig,
+    pub trace: Option<TraceConfig>,
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let mut args = args.iter().skip(1).map(String::as_str).peekable();
+    if args.next_if_eq(&"disasm").is_some() {
+        return parse_disasm(args);
+    }
+    if args.next_if_eq(&"asm").is_some() {
+        return parse_asm(args);
+    }
+    if args.next_if_eq(&"list").is_some() {
+        let paths: Vec<String> = args.map(str::to_string).collect();
+        if paths.is_empty() {
+            return Err("No directory or archive provided.".to_string());
+        }
+        return Ok(Command::List { paths });
+    }
+    if args.next_if_eq(&"info").is_some() {
+        return parse_info(args);
+    }
+    let debug = args.next_if_eq(&"debug").is_some();
+
+    let mut rom = None;
+    let mut entry = None;
+    let mut snapshot = None;
+    let mut symbols = None;
+    let mut load_address = PROG_MEM_START as u16;
+    let mut config = None;
+    let mut print_config = false;
+    let mut overrides = Layer::default();
+    let mut record = None;
+    let mut frames = None;
+    let mut watch = false;
+    let mut keep_state = false;
+    let mut rom_db = true;
+    let mut stack = StackConfig::default();
+    let mut memory = MemoryConfig::default();
+    let mut trace_path = None;
+    let mut columns = ALL_COLUMNS.to_vec();
+    let mut start = None;
+    let mut stop = None;
+    let mut trace_options_given = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg {
+            "--entry" => entry = Some(value()?.to_string()),
+            "--snapshot" => snapshot = Some(value()?.to_string()),
+            "--symbols" => symbols = Some(value()?.to_string()),
+            "--config" => config = Some(value()?.to_string()),
+            "--print-config" => print_config = true,
+            "--scale" => {
+                let scale = value()?.parse().map_err(|_| "The scale must be a number")?;
+                overrides.scale = Some(config::check_scale(scale)?);
+            }
+            "--keymap" => overrides.keymap_profile = Some(value()?.to_string()),
+            "--no-audio" => overrides.audio = Some(false),
+            "--ticks-per-frame" => {
+                let ticks = value()?.parse().unwrap_or(0);
+                overrides.ticks_per_frame = Some(config::check_ticks_per_frame(ticks)?);
+            }
+            "--fast-forward" => {
+                let multiple = value()?.parse().unwrap_or(0);
+                overrides.fast_forward = Some(config::check_fast_forward(multiple)?);
+            }
+            "--platform" => overrides.platform = Some(value()?.parse()?),
+            "--quirks" => {
+                for setting in value()?.split(',') {
+                    overrides.quirks.push(parse_quirk(setting)?);
+                }
+            }
+            "--palette" => overrides.palette = Some(palette::parse_preset(value()?)?),
+            "--colors" => {
+                let list = value()?;
+                let colors = list
+                    .split(',')
+                    .map(palette::parse_color)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let [background, fill, rest @ ..] = colors.as_slice() else {
+                    return Err(format!("Expected two or four colors, not {}", list));
+                };
+                overrides.background = Some(*background);
+                overrides.fill = Some(*fill);
+                match rest {
+                    [] => {}
+                    [fill2, blend] => {
+                        overrides.fill2 = Some(*fill2);
+                        overrides.blend = Some(*blend);
+                    }
+                    _ => return Err(format!("Expected two or four colors, not {}", list)),
+                }
+            }
+            "--record-gif" => record = Some((value()?.to_string(), Format::Gif)),
+            "--record-png" => record = Some((value()?.to_string(), Format::Png)),
+            "--frames" => {
+                let n = value()?.parse().ok().filter(|&n| n > 0);
+                frames = Some(n.ok_or("The number of frames must be at least 1")?);
+            }
+            "--watch" => watch = true,
+            "--keep-state" => keep_state = true,
+            "--hud" => overrides.hud = Some(true),
+            "--filter" => overrides.filter = Some(value()?.parse()?),
+            "--present" => overrides.present = Some(value()?.parse()?),
+            "--decay" => {
+                let frames = value()?
+                    .parse()
+                    .map_err(|_| "The decay must be a number of frames")?;
+                overrides.decay = Some(config::check_decay(frames)?);
+            }
+            "--no-rom-db" => rom_db = false,
+            "--load-address" => load_address = parse_address(value()?)?,
+            "--stack-depth" => {
+                stack.depth = value()?
+                    .parse()
+                    .ok()
+                    .filter(|depth| (1..=MAX_STACK_DEPTH).contains(depth))
+                    .ok_or(format!("The stack depth must be 1 to {}", MAX_STACK_DEPTH))?;
+            }
+            "--stack-policy" => stack.policy = value()?.parse()?,
+            "--stack-in-ram" => stack.in_ram = true,
+            "--memory-policy" => memory.policy = value()?.parse()?,
+            "--protect" => memory.protection = value()?.parse()?,
+            "--font-base" => {
+                let top = PROG_MEM_START - FONT_COUNT;
+                memory.font_base = parse_hex(value()?)
+                    .filter(|&base| base <= top)
+                    .ok_or(format!("The font base must be 0 to {:#X}", top))?
+                    as u16;
+            }
+            "--vip-memory-map" => memory.vip_layout = true,
+            "--trace" => trace_path = Some(value()?.to_string()),
+            "--trace-columns" => {
+                columns = value()?
+                    .split(',')
+                    .map(str::parse::<Column>)
+                    .collect::<Result<_, _>>()?;
+                trace_options_given = true;
+            }
+            "--trace-start" => {
+                start = Some(parse_address(value()?)?);
+                trace_options_given = true;
+            }
+            "--trace-stop" => {
+                stop = Some(parse_address(value()?)?);
+                trace_options_given = true;
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg.to_string()),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+
+    if trace_options_given && trace_path.is_none() {
+        return Err("Trace options need --trace <file>".to_string());
+    }
+
+    if frames.is_some() && record.is_none() {
+        return Err("--frames needs --record-gif or --record-png".to_string());
+    }
+
+    if keep_state && !watch {
+        return Err("--keep-state needs --watch".to_string());
+    }
+    if watch && rom.as_deref().is_none_or(|rom| rom == "-") {
+        return Err("--watch needs a ROM file".to_string());
+    }
+
+    if rom.is_none() && snapshot.is_none() && !print_config {
+        return Err("No ROM filename provided.".to_string());
+    }
+
+    let options = Options {
+        rom,
+        entry,
+        snapshot,
+        symbols,
+        load_address,
+        config,
+        print_config,
+        overrides,
+        record,
+        frames,
+        watch,
+        keep_state,
+        rom_db,
+        stack,
+        memory,
+        trace: trace_path.map(|path| TraceConfig {
+            path,
+            columns,
+            start,
+            stop,
+        }),
+    };
+    Ok(if debug {
+        Command::Debug(options)
+    } else {
+        Command::Run(options)
+    })
+}
+
+fn parse_disasm<'a>(mut args: impl Iterator<Item = &'a str>) -> Result<Command, String> {
+    let mut rom = None;
+    let mut mode = disasm::Mode::Linear;
+    let mut symbols = None;
+    let mut load_address = PROG_MEM_START as u16;
+    while let Some(arg) = args.next() {
+        match arg {
+            "--recursive" => mode = disasm::Mode::Recursive,
+            "--symbols" => {
+                symbols = Some(args.next().ok_or("--symbols needs a value")?.to_string())
+            }
+            "--load-address" => {
+                load_address = parse_address(args.next().ok_or("--load-address needs a value")?)?
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg.to_string()),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    Ok(Command::Disasm {
+        rom: rom.ok_or("No ROM filename provided.")?,
+        mode,
+        symbols,
+        load_address,
+    })
+}
+
+fn parse_info<'a>(mut args: impl Iterator<Item = &'a str>) -> Result<Command, String> {
+    let mut rom = None;
+    let mut entry = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            "--entry" => entry = Some(args.next().ok_or("--entry needs a value")?.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg.to_string()),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    Ok(Command::Info {
+        rom: rom.ok_or("No ROM filename provided.")?,
+        entry,
+    })
+}
+
+fn parse_asm<'a>(mut args: impl Iterator<Item = &'a str>) -> Result<Command, String> {
+    let mut source = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            "-o" => output = Some(args.next().ok_or("-o needs a value")?.to_string()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ if source.is_none() => source = Some(arg.to_string()),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    let source: String = source.ok_or("No source filename provided.")?;
+    let output = output.unwrap_or_else(|| {
+        Path::new(&source)
+            .with_extension("ch8")
+            .display()
+            .to_string()
+    });
+    Ok(Command::Asm { source, output })
+}
+
+// A quirk setting like `shift=on`
+fn parse_quirk(setting: &str) -> Result<(String, bool), String> {
+    let invalid = || {
+        format!(
+            "Invalid quirk setting `{}` (expected name=on or name=off)",
+            setting
+        )
+    };
+    let (name, on) = setting.split_once('=').ok_or_else(invalid)?;
+    let on = match on {
+        "on" => true,
+        "off" => false,
+        _ => return Err(invalid()),
+    };
+    // Check the name now, so a typo is reported along with the other option errors
+    Quirks::default().set(name, on)?;
+    Ok((name.to_string(), on))
+}
+
+/// Parse a hex number, with or without a leading `0x`.
+pub fn parse_hex(s: &str) -> Option<usize> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    usize::from_str_radix(digits, 16).ok()
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    parse_hex(s)
+        .and_then(|addr| u16::try_from(addr).ok())
+        .ok_or(format!("Invalid address {}", s))
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Frames are drawn at 60Hz, the rate the timers count down at.
+pub const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// The slowest slow motion runs at 1/2^this of full speed
+const MAX_SLOWDOWN: u32 = 4;
+
+/// When the window's emulated frames are due. Pausing, fast-forward and slow motion all change
+/// how fast emulated time runs against real time, rather than how much a frame does, so the
+/// timers and recordings keep time with the instructions.
+pub struct Clock {
+    // Whether frames run as a whole, a speed's worth of instructions at once, or instructions
+    // run one by one with a frame ending whenever it's time
+    paced: bool,
+    next_frame: Instant,
+    paused: bool,
+    // Running one more frame while paused
+    advancing: bool,
+    // Fast-forward runs this many frames in the time of one, while it's held
+    multiple: u32,
+    fast_forward: bool,
+    // Slow motion runs at 1/2^this of full speed
+    slowdown: u32,
+}
+
+impl Clock {
+    pub fn new(paced: bool, multiple: u32) -> Clock {
+        Clock {
+            paced,
+            next_frame: Instant::now(),
+            paused: false,
+            advancing: false,
+            multiple,
+            fast_forward: false,
+            slowdown: 0,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advancing = false;
+        // Frames missed while paused aren't caught up on
+        self.next_frame = Instant::now();
+    }
+
+    /// Run one more frame, pausing first if running.
+    pub fn advance(&mut self) {
+        self.paused = true;
+        self.advancing = true;
+        // Without a speed, instructions run for a frame's time before the frame ends
+        self.next_frame = Instant::now();
+        if !self.paced {
+            self.next_frame += self.frame_time();
+        }
+    }
+
+    pub fn set_fast_forward(&mut self, held: bool) {
+        self.fast_forward = held;
+    }
+
+    /// Halve the speed, down to a sixteenth.
+    pub fn slower(&mut self) {
+        self.slowdown = (self.slowdown + 1).min(MAX_SLOWDOWN);
+    }
+
+    /// Double the speed, back up to full.
+    pub fn faster(&mut self) {
+        self.slowdown = self.slowdown.saturating_sub(1);
+    }
+
+    pub fn is_full_speed(&self) -> bool {
+        !self.paused && self.multiple() == 1 && self.slowdown == 0
+    }
+
+    /// Whether instructions should run, rather than the machine being held paused.
+    pub fn running(&self) -> bool {
+        !self.paused || self.advancing
+    }
+
+    /// How many frames to run at once: more than one while fast-forwarding, as the window
+    /// can't be drawn any faster than it already is.
+    pub fn multiple(&self) -> u32 {
+        if self.fast_forward && !self.paused {
+            self.multiple
+        } else {
+            1
+        }
+    }
+
+    /// Stretch a real time to match emulated time in slow motion.
+    pub fn slowed(&self, time: Duration) -> Duration {
+        time * (1 << self.slowdown)
+    }
+
+    // How long one emulated frame takes in real time
+    fn frame_time(&self) -> Duration {
+        self.slowed(FRAME_TIME) / self.multiple()
+    }
+
+    /// How many frames are due to end by now, moving on to the next one after them.
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        if !self.running() {
+            return 0;
+        }
+        let mut frames = 0;
+        while now >= self.next_frame && frames < self.multiple() {
+            frames += 1;
+            self.next_frame += self.frame_time();
+        }
+        // A frontend that's fallen behind starts afresh rather than rushing to catch up
+        self.next_frame = self.next_frame.max(now);
+        if self.advancing && frames > 0 {
+            self.advancing = false;
+        }
+        frames
+    }
+
+    /// How long to sleep for before the next frame is due, which is never more than one real
+    /// frame so that keys are still read in slow motion or while paused.
+    pub fn until_next_frame(&self, now: Instant) -> Duration {
+        if self.running() {
+            self.next_frame
+                .saturating_duration_since(now)
+                .min(FRAME_TIME)
+        } else {
+            FRAME_TIME
+        }
+    }
+}
+
+// The speed, as it's shown in the window's title
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.paused {
+            write!(f, "paused")
+        } else if self.multiple() > 1 {
+            write!(f, "{}x", self.multiple)
+        } else if self.slowdown > 0 {
+            write!(f, "1/{} speed", 1 << self.slowdown)
+        } else {
+            write!(f, "full speed")
+        }
+    }
+}
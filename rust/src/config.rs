@@ -0,0 +1,631 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use crate::filter::Filter;
+use crate::keymap::{self, Keymap, BUILT_IN_PROFILES};
+use crate::palette::{self, Palette, Rgb};
+use crate::present::Presentation;
+use crate::quirks::{Platform, Quirks};
+use crate::recording::Format;
+
+/// How the emulator is run and shown: everything a config file or the command line can set.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub frontend: Frontend,
+    // The window is the display's size times this
+    pub scale: u32,
+    // Instructions per 60Hz frame, or as many as the frontend manages if not set
+    pub ticks_per_frame: Option<usize>,
+    // How long to sleep between instructions when no speed is set
+    pub cycle_sleep_us: u64,
+    // How many times full speed holding the fast-forward key runs at
+    pub fast_forward: u32,
+    pub quirks: Quirks,
+    pub palette: Palette,
+    // How many frames an erased pixel takes to fade out
+    pub decay: u32,
+    pub present: Presentation,
+    pub filter: Filter,
+    // Start with the overlay of speeds and machine state shown
+    pub hud: bool,
+    // What the record key records to
+    pub record_format: Format,
+    pub keymap: Keymap,
+    // Keymap profiles defined in config files, by name
+    pub profiles: BTreeMap<String, Bindings>,
+    // Ring the terminal bell when the ROM starts a sound. There's no sound device output
+    pub audio: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            frontend: Frontend::Window,
+            scale: 1,
+            ticks_per_frame: None,
+            cycle_sleep_us: 1,
+            fast_forward: 4,
+            quirks: Quirks::default(),
+            palette: Palette::default(),
+            decay: 0,
+            present: Presentation::Direct,
+            filter: Filter::None,
+            hud: false,
+            record_format: Format::Gif,
+            keymap: Keymap::default(),
+            profiles: BTreeMap::new(),
+            audio: true,
+        }
+    }
+}
+
+/// Hex keys and the keyboard keys to bind to each, in place of the ones it had.
+pub type Bindings = Vec<(u8, Vec<VirtualKeyCode>)>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frontend {
+    /// A window drawing the display, with the keyboard as the keypad.
+    Window,
+    /// The command-line debugger.
+    Debugger,
+}
+
+/// Settings from one place, to be laid over those from the places before it: the defaults, the
+/// global config file, the ROM's own config file, then the command line. Anything not given is
+/// left as it was.
+#[derive(Default)]
+pub struct Layer {
+    pub frontend: Option<Frontend>,
+    pub scale: Option<u32>,
+    pub ticks_per_frame: Option<usize>,
+    pub cycle_sleep_us: Option<u64>,
+    pub fast_forward: Option<u32>,
+    // Its quirks are applied before the quirks given by name
+    pub platform: Option<Platform>,
+    pub quirks: Vec<(String, bool)>,
+    // A preset, with the colors given one by one on top of it
+    pub palette: Option<Palette>,
+    pub background: Option<Rgb>,
+    pub fill: Option<Rgb>,
+    pub fill2: Option<Rgb>,
+    pub blend: Option<Rgb>,
+    pub decay: Option<u32>,
+    pub present: Option<Presentation>,
+    pub filter: Option<Filter>,
+    pub hud: Option<bool>,
+    pub record_format: Option<Format>,
+    // Profiles defined here, which are the standard layout with these bindings
+    pub profiles: Vec<(String, Bindings)>,
+    // The profile to use, built in or defined here or before, with `keys` on top of it
+    pub keymap_profile: Option<String>,
+    pub keys: Bindings,
+    pub audio: Option<bool>,
+}
+
+impl Layer {
+    pub fn apply(&self, settings: &mut Settings) -> Result<(), String> {
+        if let Some(frontend) = self.frontend {
+            settings.frontend = frontend;
+        }
+        if let Some(scale) = self.scale {
+            settings.scale = scale;
+        }
+        if self.ticks_per_frame.is_some() {
+            settings.ticks_per_frame = self.ticks_per_frame;
+        }
+        if let Some(us) = self.cycle_sleep_us {
+            settings.cycle_sleep_us = us;
+        }
+        if let Some(multiple) = self.fast_forward {
+            settings.fast_forward = multiple;
+        }
+        if let Some(platform) = self.platform {
+            settings.quirks = platform.quirks();
+        }
+        for (name, on) in &self.quirks {
+            settings
+                .quirks
+                .set(name, *on)
+                .expect("quirk names are checked when a layer is made");
+        }
+        if let Some(palette) = self.palette {
+            settings.palette = palette;
+        }
+        let colors = [
+            (self.background, &mut settings.palette.background),
+            (self.fill, &mut settings.palette.fill),
+            (self.fill2, &mut settings.palette.fill2),
+            (self.blend, &mut settings.palette.blend),
+        ];
+        for (color, setting) in colors {
+            if let Some(color) = color {
+                *setting = color;
+            }
+        }
+        if let Some(decay) = self.decay {
+            settings.decay = decay;
+        }
+        if let Some(present) = self.present {
+            settings.present = present;
+        }
+        if let Some(filter) = self.filter {
+            settings.filter = filter;
+        }
+        if let Some(hud) = self.hud {
+            settings.hud = hud;
+        }
+        if let Some(format) = self.record_format {
+            settings.record_format = format;
+        }
+        for (name, bindings) in &self.profiles {
+            settings.profiles.insert(name.clone(), bindings.clone());
+        }
+        if let Some(name) = &self.keymap_profile {
+            settings.keymap = profile(name, &settings.profiles)?;
+        }
+        for (hex, keys) in &self.keys {
+            settings.keymap.bind(*hex, keys);
+        }
+        if let Some(audio) = self.audio {
+            settings.audio = audio;
+        }
+        Ok(())
+    }
+
+    /// Read a config file, like:
+    /// ```toml
+    /// frontend = "window"   # or "debugger"
+    /// scale = 10
+    /// platform = "schip"
+    ///
+    /// [speed]
+    /// ticks_per_frame = 15
+    /// fast_forward = 8      # how many times full speed holding Tab runs at
+    ///
+    /// [quirks]
+    /// shift = false
+    ///
+    /// [palette]
+    /// preset = "amber"      # or mono, green, lcd, or an XO-CHIP set: octo, hotdog, gray, cga0, cga1
+    /// fill = "#33FF66"      # and background, and fill2 and blend for XO-CHIP's second plane
+    ///
+    /// [display]
+    /// decay = 3             # frames an erased pixel takes to fade out
+    /// present = "lazy"      # or direct, clear or blend, to hide flicker
+    /// filter = "scale2x"    # or none, scale3x, epx or scanlines
+    /// hud = true            # start with the overlay F3 shows and hides
+    ///
+    /// [record]
+    /// format = "png"        # what F9 records to: gif, or png for a directory of frames
+    ///
+    /// [keymap]
+    /// profile = "arrows"    # or "standard" (1234/QWER/ASDF/ZXCV, the default) or "legacy"
+    /// 6 = ["E", "Space"]
+    ///
+    /// [keymaps.arrows]
+    /// 2 = "Up"
+    /// 8 = "Down"
+    ///
+    /// [audio]
+    /// enabled = false
+    /// ```
+    pub fn load(path: &Path) -> Result<Layer, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Layer::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(text: &str) -> Result<Layer, String> {
+        let file: File = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        let mut quirks = Vec::new();
+        for (name, on) in file.quirks {
+            Quirks::default().set(&name, on)?;
+            quirks.push((name, on));
+        }
+        let mut keymap_profile = None;
+        let mut keys = Vec::new();
+        for (name, bound) in file.keymap {
+            match (name.as_str(), bound) {
+                ("profile", KeyNames::One(profile)) => keymap_profile = Some(profile),
+                ("profile", KeyNames::Several(_)) => {
+                    return Err("The keymap profile must be a name".to_string())
+                }
+                (hex, bound) => keys.push(parse_binding(hex, bound)?),
+            }
+        }
+        let mut profiles = Vec::new();
+        for (name, table) in file.keymaps {
+            if BUILT_IN_PROFILES.contains(&name.as_str()) {
+                return Err(format!("The keymap profile `{}` is built in", name));
+            }
+            let bindings = table
+                .into_iter()
+                .map(|(hex, bound)| parse_binding(&hex, bound))
+                .collect::<Result<_, String>>()
+                .map_err(|e| format!("In keymap profile `{}`: {}", name, e))?;
+            profiles.push((name, bindings));
+        }
+        let color = |color: &Option<String>| color.as_deref().map(palette::parse_color).transpose();
+
+        Ok(Layer {
+            frontend: file.frontend.as_deref().map(str::parse).transpose()?,
+            scale: file.scale.map(check_scale).transpose()?,
+            ticks_per_frame: file
+                .speed
+                .ticks_per_frame
+                .map(check_ticks_per_frame)
+                .transpose()?,
+            cycle_sleep_us: file.speed.cycle_sleep_us,
+            fast_forward: file
+                .speed
+                .fast_forward
+                .map(check_fast_forward)
+                .transpose()?,
+            platform: file.platform.as_deref().map(str::parse).transpose()?,
+            quirks,
+            palette: file
+                .palette
+                .preset
+                .as_deref()
+                .map(palette::parse_preset)
+                .transpose()?,
+            background: color(&file.palette.background)?,
+            fill: color(&file.palette.fill)?,
+            fill2: color(&file.palette.fill2)?,
+            blend: color(&file.palette.blend)?,
+            decay: file.display.decay.map(check_decay).transpose()?,
+            present: file
+                .display
+                .present
+                .as_deref()
+                .map(str::parse)
+                .transpose()?,
+            filter: file.display.filter.as_deref().map(str::parse).transpose()?,
+            hud: file.display.hud,
+            record_format: file.record.format.as_deref().map(str::parse).transpose()?,
+            profiles,
+            keymap_profile,
+            keys,
+            audio: file.audio.enabled,
+        })
+    }
+}
+
+// A config file as written
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    frontend: Option<String>,
+    scale: Option<u32>,
+    platform: Option<String>,
+    speed: Speed,
+    quirks: BTreeMap<String, bool>,
+    palette: Colors,
+    display: Display,
+    record: Record,
+    keymap: BTreeMap<String, KeyNames>,
+    keymaps: BTreeMap<String, BTreeMap<String, KeyNames>>,
+    audio: Audio,
+}
+
+// A hex key's binding, to one keyboard key or a list of them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyNames {
+    One(String),
+    Several(Vec<String>),
+}
+
+fn parse_binding(hex: &str, bound: KeyNames) -> Result<(u8, Vec<VirtualKeyCode>), String> {
+    let hex = u8::from_str_radix(hex, 16)
+        .ok()
+        .filter(|&k| k < 16)
+        .ok_or(format!("`{}` is not a hex key", hex))?;
+    let names = match bound {
+        KeyNames::One(name) => vec![name],
+        KeyNames::Several(names) => names,
+    };
+    let keys = names
+        .iter()
+        .map(|name| keymap::parse_key(name))
+        .collect::<Result<_, _>>()?;
+    Ok((hex, keys))
+}
+
+/// A keymap profile, built in or defined in a config file.
+pub fn profile(name: &str, defined: &BTreeMap<String, Bindings>) -> Result<Keymap, String> {
+    if let Some(keymap) = Keymap::built_in(name) {
+        return Ok(keymap);
+    }
+    let bindings = defined.get(name).ok_or_else(|| {
+        let names: Vec<&str> = BUILT_IN_PROFILES
+            .iter()
+            .copied()
+            .chain(defined.keys().map(String::as_str))
+            .collect();
+        format!(
+            "Unknown keymap profile `{}` (expected {})",
+            name,
+            names.join(", ")
+        )
+    })?;
+    let mut keymap = Keymap::default();
+    for (hex, keys) in bindings {
+        keymap.bind(*hex, keys);
+    }
+    Ok(keymap)
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Speed {
+    ticks_per_frame: Option<usize>,
+    cycle_sleep_us: Option<u64>,
+    fast_forward: Option<u32>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Colors {
+    preset: Option<String>,
+    background: Option<String>,
+    fill: Option<String>,
+    fill2: Option<String>,
+    blend: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Display {
+    decay: Option<u32>,
+    present: Option<String>,
+    filter: Option<String>,
+    hud: Option<bool>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Record {
+    format: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Audio {
+    enabled: Option<bool>,
+}
+
+pub fn check_scale(scale: u32) -> Result<u32, String> {
+    if (1..=64).contains(&scale) {
+        Ok(scale)
+    } else {
+        Err("The scale must be 1 to 64".to_string())
+    }
+}
+
+pub fn check_ticks_per_frame(ticks: usize) -> Result<usize, String> {
+    if ticks > 0 {
+        Ok(ticks)
+    } else {
+        Err("The ticks per frame must be at least 1".to_string())
+    }
+}
+
+pub fn check_fast_forward(multiple: u32) -> Result<u32, String> {
+    if (2..=32).contains(&multiple) {
+        Ok(multiple)
+    } else {
+        Err("The fast-forward multiple must be 2 to 32".to_string())
+    }
+}
+
+// Past this, a pixel already fades by as little as it can each frame
+pub fn check_decay(frames: u32) -> Result<u32, String> {
+    if frames <= 255 {
+        Ok(frames)
+    } else {
+        Err("The decay must be 0 to 255 frames".to_string())
+    }
+}
+
+/// The global config file, in `$XDG_CONFIG_HOME/chip-8` or `~/.config/chip-8`.
+pub fn global_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("chip-8").join("config.toml"))
+}
+
+/// The config file for one ROM, next to it with a .toml extension like its symbol file.
+pub fn rom_path(rom: &Path) -> PathBuf {
+    rom.with_extension("toml")
+}
+
+// The settings as a config file, which reads back in as the same settings
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "frontend = \"{}\"", self.frontend)?;
+        writeln!(f, "scale = {}", self.scale)?;
+
+        writeln!(f, "\n[speed]")?;
+        match self.ticks_per_frame {
+            Some(ticks) => writeln!(f, "ticks_per_frame = {}", ticks)?,
+            None => writeln!(
+                f,
+                "# No ticks_per_frame, so instructions run as fast as they can"
+            )?,
+        }
+        writeln!(f, "cycle_sleep_us = {}", self.cycle_sleep_us)?;
+        writeln!(f, "fast_forward = {}", self.fast_forward)?;
+
+        let quirks = &self.quirks;
+        writeln!(f, "\n[quirks]")?;
+        writeln!(f, "shift = {}", quirks.shift)?;
+        writeln!(f, "load-store = {}", quirks.load_store)?;
+        writeln!(f, "jump = {}", quirks.jump)?;
+        writeln!(f, "logic = {}", quirks.logic)?;
+        writeln!(f, "clip = {}", quirks.clip)?;
+        writeln!(f, "vf-order = {}", quirks.vf_order)?;
+
+        writeln!(f, "\n[palette]")?;
+        let colors = [
+            ("background", self.palette.background),
+            ("fill", self.palette.fill),
+            ("fill2", self.palette.fill2),
+            ("blend", self.palette.blend),
+        ];
+        for (name, color) in colors {
+            writeln!(f, "{} = \"{}\"", name, palette::format_color(color))?;
+        }
+
+        writeln!(f, "\n[display]")?;
+        writeln!(f, "decay = {}", self.decay)?;
+        writeln!(f, "present = \"{}\"", self.present)?;
+        writeln!(f, "filter = \"{}\"", self.filter)?;
+        writeln!(f, "hud = {}", self.hud)?;
+
+        writeln!(f, "\n[record]")?;
+        writeln!(f, "format = \"{}\"", self.record_format)?;
+
+        writeln!(f, "\n[keymap]")?;
+        for hex in 0..16 {
+            let names: Vec<String> = self
+                .keymap
+                .keys(hex)
+                .iter()
+                .map(|&key| format!("\"{}\"", keymap::key_name(key)))
+                .collect();
+            writeln!(f, "{:X} = [{}]", hex, names.join(", "))?;
+        }
+
+        writeln!(f, "\n[audio]")?;
+        writeln!(f, "enabled = {}", self.audio)
+    }
+}
+
+impl FromStr for Frontend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Frontend, String> {
+        match s {
+            "window" => Ok(Frontend::Window),
+            "debugger" => Ok(Frontend::Debugger),
+            _ => Err(format!(
+                "Unknown frontend `{}` (expected window or debugger)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Frontend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Frontend::Window => write!(f, "window"),
+            Frontend::Debugger => write!(f, "debugger"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printed_settings_read_back_the_same() {
+        let mut settings = Settings {
+            frontend: Frontend::Debugger,
+            scale: 3,
+            ticks_per_frame: Some(12),
+            fast_forward: 8,
+            quirks: Platform::Chip8.quirks(),
+            palette: palette::parse_preset("amber").unwrap(),
+            decay: 5,
+            filter: Filter::Scale2x,
+            hud: true,
+            audio: false,
+            ..Settings::default()
+        };
+        settings
+            .keymap
+            .bind(0xA, &[VirtualKeyCode::Space, VirtualKeyCode::Z]);
+        let printed = settings.to_string();
+
+        let mut read = Settings::default();
+        Layer::parse(&printed).unwrap().apply(&mut read).unwrap();
+        assert_eq!(read.to_string(), printed);
+        assert_eq!(read.quirks, settings.quirks);
+        assert_eq!(read.keymap, settings.keymap);
+    }
+
+    // Lay config files over the defaults in order
+    fn layered(files: &[&str]) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        for text in files {
+            Layer::parse(text)?.apply(&mut settings)?;
+        }
+        Ok(settings)
+    }
+
+    #[test]
+    fn later_layers_win_and_unset_settings_are_left_alone() {
+        let global = r##"
+            scale = 2
+            platform = "chip8"
+            [speed]
+            ticks_per_frame = 15
+            [quirks]
+            clip = false
+            [palette]
+            preset = "amber"
+        "##;
+        let rom = r##"
+            scale = 4
+            [quirks]
+            jump = true
+            [palette]
+            fill = "#FF0000"
+        "##;
+        let settings = layered(&[global, rom]).unwrap();
+        assert_eq!(settings.scale, 4);
+        assert_eq!(settings.ticks_per_frame, Some(15));
+        assert_eq!(settings.fast_forward, Settings::default().fast_forward);
+
+        // The platform's quirks, then each layer's named ones over them
+        let mut quirks = Platform::Chip8.quirks();
+        quirks.clip = false;
+        quirks.jump = true;
+        assert_eq!(settings.quirks, quirks);
+
+        // The preset, with one color replaced
+        let mut palette = palette::parse_preset("amber").unwrap();
+        palette.fill = [0xFF, 0, 0];
+        assert_eq!(settings.palette, palette);
+
+        // A platform in a later layer starts the quirks afresh
+        let settings = layered(&[global, rom, "platform = \"schip\""]).unwrap();
+        assert_eq!(settings.quirks, Platform::Schip.quirks());
+    }
+
+    #[test]
+    fn bad_config_files_are_rejected() {
+        for text in [
+            "scale = 0",
+            "colour = 1",
+            "[quirks]\nwobble = true",
+            "[display]\ndecay = 256",
+            "[speed]\nfast_forward = 1",
+        ] {
+            assert!(layered(&[text]).is_err(), "{}", text);
+        }
+    }
+}
@@ -1,11 +1,16 @@
-use crate::{ram::RAM, stack::Stack, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::delta::{Change, Delta};
+use crate::error::EmulationError;
+use crate::instruction::Instruction;
+use crate::keypad::Keypad;
+use crate::quirks::Quirks;
+use crate::{
+    ram::Address, ram::MEM_BYTES, ram::RAM, stack::Stack, Vram, DISPLAY_HEIGHT, DISPLAY_WIDTH,
+};
 use rand::{prelude::ThreadRng, Rng};
-use winit::event::DeviceEvent;
-use winit::event::Event;
-use winit::event::VirtualKeyCode;
 
 const NUM_REGISTERS: usize = 16;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     pub general_registers: [u8; NUM_REGISTERS],
     pub delay_timer: u8,
@@ -13,6 +18,7 @@ pub struct CPU {
     pub program_counter: u16,
     pub index_register: u16,
     pub rng: ThreadRng,
+    pub quirks: Quirks,
 }
 
 impl CPU {
@@ -24,131 +30,177 @@ impl CPU {
             sound_timer: 0,
             index_register: 0,
             rng: rand::thread_rng(),
+            quirks: Quirks::default(),
         }
     }
 
-    pub fn increment_pc(&mut self) {
-        self.program_counter += 2;
-    }
-
-    pub fn decrement_pc(&mut self) {
-        self.program_counter -= 2;
-    }
-
-    pub fn lookup_key_code(&self, value: u8) -> Result<VirtualKeyCode, &'static str> {
-        match value {
-            0x0 => Ok(VirtualKeyCode::Key1),
-            0x1 => Ok(VirtualKeyCode::Key2),
-            0x2 => Ok(VirtualKeyCode::Key3),
-            0x3 => Ok(VirtualKeyCode::C),
-            0x4 => Ok(VirtualKeyCode::Key4),
-            0x5 => Ok(VirtualKeyCode::Key5),
-            0x6 => Ok(VirtualKeyCode::Key6),
-            0x7 => Ok(VirtualKeyCode::D),
-            0x8 => Ok(VirtualKeyCode::Key7),
-            0x9 => Ok(VirtualKeyCode::Key8),
-            0xA => Ok(VirtualKeyCode::Key9),
-            0xB => Ok(VirtualKeyCode::E),
-            0xC => Ok(VirtualKeyCode::A),
-            0xD => Ok(VirtualKeyCode::Key0),
-            0xE => Ok(VirtualKeyCode::B),
-            0xF => Ok(VirtualKeyCode::F),
-            _ => Err("Invalid hex value for keypad lookup"),
-        }
+    /// Count the timers down, as happens 60 times a second. This is the frontend's doing rather
+    /// than an instruction's, so it isn't recorded in any delta.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
     }
 
-    pub fn lookup_hex_of_key(&self, key: VirtualKeyCode) -> Result<u8, &'static str> {
-        match key {
-            VirtualKeyCode::Key1 => Ok(0x0),
-            VirtualKeyCode::Key2 => Ok(0x1),
-            VirtualKeyCode::Key3 => Ok(0x2),
-            VirtualKeyCode::Key4 => Ok(0x4),
-            VirtualKeyCode::Key5 => Ok(0x5),
-            VirtualKeyCode::Key6 => Ok(0x6),
-            VirtualKeyCode::Key7 => Ok(0x8),
-            VirtualKeyCode::Key8 => Ok(0x9),
-            VirtualKeyCode::Key9 => Ok(0xA),
-            VirtualKeyCode::Key0 => Ok(0xD),
-            VirtualKeyCode::A => Ok(0xC),
-            VirtualKeyCode::C => Ok(0x3),
-            VirtualKeyCode::D => Ok(0x7),
-            VirtualKeyCode::E => Ok(0xB),
-            _ => Err("Invalid key"),
-        }
+    /// Count the timers down like `tick_timers`, recording what they were in `delta` so that
+    /// stepping back puts them back.
+    pub fn tick_timers_undoably(&mut self, delta: &mut Delta) {
+        delta.record(Change::DelayTimer(self.delay_timer));
+        delta.record(Change::SoundTimer(self.sound_timer));
+        self.tick_timers();
+    }
+
+    /// Move the PC on to the next instruction. Running past the top of the address space is out
+    /// of bounds, unless the memory policy wraps it around to the bottom.
+    pub fn increment_pc(&mut self, delta: &mut Delta, ram: &RAM) -> Result<(), EmulationError> {
+        let next = ram.offset(Address(self.program_counter), 2)?;
+        self.set_pc(delta, next.0);
+        Ok(())
+    }
+
+    // Go back to the instruction just fetched, which `increment_pc` may have wrapped past
+    fn decrement_pc(&mut self, delta: &mut Delta) {
+        let previous = self.program_counter.wrapping_sub(2) % MEM_BYTES as u16;
+        self.set_pc(delta, previous);
+    }
+
+    // Every write to machine state goes through one of these so the old value lands in the
+    // instruction's undo delta.
+
+    fn set_pc(&mut self, delta: &mut Delta, addr: u16) {
+        delta.record(Change::ProgramCounter(self.program_counter));
+        self.program_counter = addr;
     }
 
-    pub fn emulate_cycle<T>(
+    fn set_register(&mut self, delta: &mut Delta, x: u8, val: u8) {
+        delta.record(Change::Register {
+            x: x as usize,
+            old: self.general_registers[x as usize],
+        });
+        self.general_registers[x as usize] = val;
+    }
+
+    fn set_index(&mut self, delta: &mut Delta, val: u16) {
+        delta.record(Change::Index(self.index_register));
+        self.index_register = val;
+    }
+
+    // A write by the ROM, which is checked against the memory map's read-only regions
+    fn write_memory(
+        &self,
+        delta: &mut Delta,
+        ram: &mut RAM,
+        addr: Address,
+        val: u8,
+    ) -> Result<(), EmulationError> {
+        ram.check_write(addr)?;
+        self.write_interpreter_memory(delta, ram, addr, val)
+    }
+
+    // A write by the interpreter itself, such as keeping the stack in RAM, which can go anywhere
+    fn write_interpreter_memory(
+        &self,
+        delta: &mut Delta,
+        ram: &mut RAM,
+        addr: Address,
+        val: u8,
+    ) -> Result<(), EmulationError> {
+        let index = ram.resolve(addr)?;
+        delta.record(Change::Memory {
+            addr: index,
+            old: ram.bytes()[index],
+        });
+        ram.write(addr, val)
+    }
+
+    fn flip_pixel(&self, delta: &mut Delta, vram: &mut Vram, x: usize, y: usize) {
+        delta.record(Change::Pixel { x, y });
+        vram[y][x] ^= 1;
+    }
+
+    /// Run a single instruction, returning everything it changed so it can be undone.
+    pub fn emulate_cycle(
         &mut self,
-        event: &Event<T>,
+        keypad: &mut Keypad,
         stack: &mut Stack,
-        vram: &mut [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+        vram: &mut Vram,
         ram: &mut RAM,
-    ) {
+    ) -> Result<Delta, EmulationError> {
+        let mut delta = Delta::new();
+
         // Each instruction is 2 bytes
         // Fetch the next instruction from memory at the PC and increment it
-        let opcode = ram.get_instruction(self.program_counter);
-        self.increment_pc();
+        let opcode = ram.read_word(Address(self.program_counter))?;
+        if let Err(e) = self.increment_pc(&mut delta, ram) {
+            delta.revert(self, keypad, stack, vram, ram);
+            return Err(e);
+        }
 
         // Decode
-        let nnn = opcode & 0x0FFF;
-        let nn = (opcode & 0x00FF) as u8;
-        let nibbles = (
-            (opcode & 0xF000) >> 12,
-            (opcode & 0x0F00) >> 8,
-            (opcode & 0x00F0) >> 4,
-            (opcode & 0x000F),
-        );
+        let result = match Instruction::decode(opcode) {
+            Some(instruction) => self.execute(&mut delta, instruction, keypad, stack, vram, ram),
+            None => Err(EmulationError::UnknownOpcode(opcode)),
+        };
+
+        // Roll back a faulting instruction, so the machine is left exactly as it was when it
+        // tried to run it
+        if let Err(e) = result {
+            delta.revert(self, keypad, stack, vram, ram);
+            return Err(e);
+        }
+        Ok(delta)
+    }
 
+    fn execute(
+        &mut self,
+        delta: &mut Delta,
+        instruction: Instruction,
+        keypad: &mut Keypad,
+        stack: &mut Stack,
+        vram: &mut Vram,
+        ram: &mut RAM,
+    ) -> Result<(), EmulationError> {
         // Execute
-        match nibbles {
-            (0x0, 0x0, 0xE, 0x0) => self.op_clear_screen(vram),
-            (0x0, 0x0, 0xE, 0xE) => self.op_return_from_subroutine(stack),
-            (0x1, _, _, _) => self.op_jump(nnn),
-            (0x2, _, _, _) => self.op_subroutine(stack, nnn),
-            (0x3, x, _, _) => self.op_skip_if_eq(x, nn.into()),
-            (0x4, x, _, _) => self.op_skip_if_not_eq(x, nn.into()),
-            (0x6, x, _, _) => self.general_registers[x as usize] = nn,
-            (0x5, x, y, _) => self.op_skip_if_eq_reg(x, y),
-            (0x7, x, _, _) => self.op_add(x, nn),
-            (0x8, x, y, 0) => self.op_set_vx_to_vy(x, y),
-            (0x8, x, y, 0x1) => self.op_binary_or(x, y),
-            (0x8, x, y, 0x2) => self.op_binary_and(x, y),
-            (0x8, x, y, 0x3) => self.op_logical_xor(x, y),
-            (0x8, x, y, 0x4) => self.op_add_with_carry(x, y),
-            (0x8, x, y, 0x5) => self.op_subtract_vy_from_vx(x, y),
-            (0x8, x, y, 0x7) => self.op_subtract_vx_from_vy(x, y),
-            (0x8, x, y, 6) => self.op_shift_right(x, y),
-            (0x8, x, y, 0xE) => self.op_shift_left(x, y),
-            (0x9, x, y, _) => self.op_skip_if_not_eq_reg(x, y),
-            (0xA, _, _, _) => self.op_set_index(nnn),
-            (0xB, _, _, _) => self.op_jump_location_plus_reg(nnn),
-            (0xC, x, _, _) => self.op_rand_and(x, nn),
-            (0xD, x, y, n) => self.op_display_vram(vram, ram, x, y, n as u8),
-            (0xE, x, 0x9, 0xE) => self.op_skip_if_pressed(&event, x),
-            (0xE, x, 0xA, 0x1) => self.op_skip_if_not_pressed(&event, x),
-            (0xF, x, 0x0, 0x7) => self.op_set_to_delay(x),
-            (0xF, x, 0x1, 0x5) => self.op_set_delay_to(x),
-            (0xF, x, 0x1, 0x8) => self.op_set_sound_to(x),
-            (0xF, x, 0x1, 0xE) => self.op_add_to_index(x),
-            (0xF, x, 0x0, 0xA) => self.op_get_key(&event, x),
-            (0xF, x, 0x5, 0x5) => self.op_store_memory(ram, x),
-            (0xF, x, 0x6, 0x5) => self.op_load_memory(ram, x),
-            (0xF, x, 0x2, 0x9) => self.op_font_character(x),
-            (0xF, x, 0x3, 0x3) => self.op_coded_dec_conv(ram, x),
-            _ => panic!(
-                "Unknown opcode ({:#01x} {:#01x} {:#01x} {:#01x})",
-                nibbles.0, nibbles.1, nibbles.2, nibbles.3
-            ),
+        match instruction {
+            Instruction::MachineCall { nnn } => return Err(EmulationError::MachineCall(nnn)),
+            Instruction::ClearScreen => self.op_clear_screen(delta, vram),
+            Instruction::Return => self.op_return_from_subroutine(delta, stack, ram)?,
+            Instruction::Jump { nnn } => self.op_jump(delta, nnn),
+            Instruction::Call { nnn } => self.op_subroutine(delta, stack, ram, nnn)?,
+            Instruction::SkipIfEq { x, nn } => self.op_skip_if_eq(delta, ram, x, nn)?,
+            Instruction::SkipIfNotEq { x, nn } => self.op_skip_if_not_eq(delta, ram, x, nn)?,
+            Instruction::SkipIfEqReg { x, y } => self.op_skip_if_eq_reg(delta, ram, x, y)?,
+            Instruction::SetRegister { x, nn } => self.op_set_register(delta, x, nn),
+            Instruction::Add { x, nn } => self.op_add(delta, x, nn),
+            Instruction::SetVxToVy { x, y } => self.op_set_vx_to_vy(delta, x, y),
+            Instruction::Or { x, y } => self.op_binary_or(delta, x, y),
+            Instruction::And { x, y } => self.op_binary_and(delta, x, y),
+            Instruction::Xor { x, y } => self.op_logical_xor(delta, x, y),
+            Instruction::AddWithCarry { x, y } => self.op_add_with_carry(delta, x, y),
+            Instruction::SubtractVyFromVx { x, y } => self.op_subtract_vy_from_vx(delta, x, y),
+            Instruction::ShiftRight { x, y } => self.op_shift_right(delta, x, y),
+            Instruction::SubtractVxFromVy { x, y } => self.op_subtract_vx_from_vy(delta, x, y),
+            Instruction::ShiftLeft { x, y } => self.op_shift_left(delta, x, y),
+            Instruction::SkipIfNotEqReg { x, y } => self.op_skip_if_not_eq_reg(delta, ram, x, y)?,
+            Instruction::SetIndex { nnn } => self.op_set_index(delta, nnn),
+            Instruction::JumpPlusV0 { nnn } => self.op_jump_location_plus_reg(delta, nnn),
+            Instruction::Random { x, nn } => self.op_rand_and(delta, x, nn),
+            Instruction::Draw { x, y, n } => self.op_display_vram(delta, vram, ram, x, y, n)?,
+            Instruction::SkipIfPressed { x } => self.op_skip_if_pressed(delta, ram, keypad, x)?,
+            Instruction::SkipIfNotPressed { x } => {
+                self.op_skip_if_not_pressed(delta, ram, keypad, x)?
+            }
+            Instruction::GetDelay { x } => self.op_set_to_delay(delta, x),
+            Instruction::GetKey { x } => self.op_get_key(delta, keypad, x),
+            Instruction::SetDelay { x } => self.op_set_delay_to(delta, x),
+            Instruction::SetSound { x } => self.op_set_sound_to(delta, x),
+            Instruction::AddToIndex { x } => self.op_add_to_index(delta, x),
+            Instruction::FontCharacter { x } => self.op_font_character(delta, ram, x),
+            Instruction::BinaryCodedDecimal { x } => self.op_coded_dec_conv(delta, ram, x)?,
+            Instruction::StoreMemory { x } => self.op_store_memory(delta, ram, x)?,
+            Instruction::LoadMemory { x } => self.op_load_memory(delta, ram, x)?,
         }
 
-        // Log opcode
-        println!(
-            "OP: {:#01x} {:#01x} {:#01x} {:#01x}",
-            nibbles.0, nibbles.1, nibbles.2, nibbles.3
-        );
-
-        // Sleep to slow execution to a reasonable rate
-        std::thread::sleep(std::time::Duration::new(0, 1000));
+        Ok(())
     }
 
     ///////////////////////////////////////////////////////////////////////////////////////////////
@@ -157,10 +209,12 @@ impl CPU {
 
     // CLS - 00E0
     // Clear the screen of the display (set all the pixels to 'off')
-    fn op_clear_screen(&mut self, vram: &mut [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT]) {
-        for x in vram {
-            for y in x {
-                *y = 0;
+    fn op_clear_screen(&mut self, delta: &mut Delta, vram: &mut Vram) {
+        for y in 0..DISPLAY_HEIGHT {
+            for x in 0..DISPLAY_WIDTH {
+                if vram[y][x] != 0 {
+                    self.flip_pixel(delta, vram, x, y);
+                }
             }
         }
     }
@@ -168,15 +222,30 @@ impl CPU {
     // 00EE: Subroutine return
     // Returning from a subroutine is done with 00EE, and it does this by removing (“popping”)
     // the last address from the stack and setting the PC to it.
-    fn op_return_from_subroutine(&mut self, stack: &mut Stack) {
-        self.program_counter = stack.pop();
+    fn op_return_from_subroutine(
+        &mut self,
+        delta: &mut Delta,
+        stack: &mut Stack,
+        ram: &RAM,
+    ) -> Result<(), EmulationError> {
+        let old_pointer = stack.depth();
+        let Some((slot, mut addr)) = stack.pop()? else {
+            return Ok(());
+        };
+        delta.record(Change::StackPointer(old_pointer));
+        // A stack kept in RAM is read back from there, in case the ROM has changed it
+        if let Some(at) = stack.ram_address(slot) {
+            addr = ram.read_word(at)?;
+        }
+        self.set_pc(delta, addr);
+        Ok(())
     }
 
     // 1NNN: Jump
     // This instruction should simply set PC to NNN, causing the program to jump to that memory
     // location. Do not increment the PC afterwards, it jumps directly there.
-    fn op_jump(&mut self, nnn: u16) {
-        self.program_counter = nnn
+    fn op_jump(&mut self, delta: &mut Delta, nnn: u16) {
+        self.set_pc(delta, nnn)
     }
 
     // 2NNN: Subroutine
@@ -184,9 +253,26 @@ impl CPU {
     // you should set PC to NNN. However, the difference between a jump and a call is that this
     // instruction should first should push the current PC to the stack, so the subroutine can
     /// return later.
-    fn op_subroutine(&mut self, stack: &mut Stack, nnn: u16) {
-        stack.push(self.program_counter);
-        self.program_counter = nnn;
+    fn op_subroutine(
+        &mut self,
+        delta: &mut Delta,
+        stack: &mut Stack,
+        ram: &mut RAM,
+        nnn: u16,
+    ) -> Result<(), EmulationError> {
+        let old_pointer = stack.depth();
+        if let Some((slot, old)) = stack.push(self.program_counter)? {
+            delta.record(Change::StackPointer(old_pointer));
+            delta.record(Change::StackEntry { slot, old });
+            if let Some(at) = stack.ram_address(slot) {
+                let [high, low] = self.program_counter.to_be_bytes();
+                self.write_interpreter_memory(delta, ram, at, high)?;
+                let next = ram.offset(at, 1)?;
+                self.write_interpreter_memory(delta, ram, next, low)?;
+            }
+        }
+        self.set_pc(delta, nnn);
+        Ok(())
     }
 
     // Skip group. These instructions do the same thing: They either do nothing, or they skip one
@@ -194,83 +280,140 @@ impl CPU {
     // above, they will obviously increment PC by either 4 or 2.
     //
     // 3XNN will skip one instruction if the value in VX is equal to NN
-    fn op_skip_if_eq(&mut self, x: u16, nn: u16) {
-        let vx: u16 = self.general_registers[x as usize].into();
+    fn op_skip_if_eq(
+        &mut self,
+        delta: &mut Delta,
+        ram: &RAM,
+        x: u8,
+        nn: u8,
+    ) -> Result<(), EmulationError> {
+        let vx = self.general_registers[x as usize];
         if vx == nn {
-            self.increment_pc();
+            self.increment_pc(delta, ram)?;
         }
+        Ok(())
     }
     // 4XNN will skip if they are not equal.
-    fn op_skip_if_not_eq(&mut self, x: u16, nn: u16) {
-        let vx: u16 = self.general_registers[x as usize].into();
+    fn op_skip_if_not_eq(
+        &mut self,
+        delta: &mut Delta,
+        ram: &RAM,
+        x: u8,
+        nn: u8,
+    ) -> Result<(), EmulationError> {
+        let vx = self.general_registers[x as usize];
         if vx != nn {
-            self.increment_pc();
+            self.increment_pc(delta, ram)?;
         }
+        Ok(())
     }
 
     // 5XY0 skips if the values in VX and VY are equal
-    fn op_skip_if_eq_reg(&mut self, x: u16, y: u16) {
+    fn op_skip_if_eq_reg(
+        &mut self,
+        delta: &mut Delta,
+        ram: &RAM,
+        x: u8,
+        y: u8,
+    ) -> Result<(), EmulationError> {
         if self.general_registers[x as usize] == self.general_registers[y as usize] {
-            self.increment_pc();
+            self.increment_pc(delta, ram)?;
         }
+        Ok(())
     }
     // ... while 9XY0 skips if they are not equal.
-    fn op_skip_if_not_eq_reg(&mut self, x: u16, y: u16) {
+    fn op_skip_if_not_eq_reg(
+        &mut self,
+        delta: &mut Delta,
+        ram: &RAM,
+        x: u8,
+        y: u8,
+    ) -> Result<(), EmulationError> {
         if self.general_registers[x as usize] != self.general_registers[y as usize] {
-            self.increment_pc();
+            self.increment_pc(delta, ram)?;
         }
+        Ok(())
+    }
+
+    // 6XNN: Set
+    // Set the register VX to the value NN.
+    fn op_set_register(&mut self, delta: &mut Delta, x: u8, nn: u8) {
+        self.set_register(delta, x, nn);
     }
 
     // 7XNN: Add
     // Add the value NN to VX.
-    fn op_add(&mut self, x: u16, nn: u8) {
-        self.general_registers[x as usize] = self.general_registers[x as usize].wrapping_add(nn);
+    fn op_add(&mut self, delta: &mut Delta, x: u8, nn: u8) {
+        let vx = self.general_registers[x as usize];
+        self.set_register(delta, x, vx.wrapping_add(nn));
     }
 
     // 8XY0: Set
     // Set VX to the value of VY
-    fn op_set_vx_to_vy(&mut self, x: u16, y: u16) {
-        self.general_registers[x as usize] = self.general_registers[y as usize];
+    fn op_set_vx_to_vy(&mut self, delta: &mut Delta, x: u8, y: u8) {
+        self.set_register(delta, x, self.general_registers[y as usize]);
     }
 
     // 8XY1: Binary OR
     // VX is set to the bitwise/binary logical disjunction (OR) of VX and VY. VY is not affected.
-    fn op_binary_or(&mut self, x: u16, y: u16) {
+    fn op_binary_or(&mut self, delta: &mut Delta, x: u8, y: u8) {
         let vx = self.general_registers[x as usize];
         let vy = self.general_registers[y as usize];
 
-        self.general_registers[x as usize] = vx | vy;
+        self.set_register(delta, x, vx | vy);
+        self.reset_flag_for_logic(delta);
     }
 
     // 8XY2: Binary AND
     // VX is set to AND of VX and VY
-    fn op_binary_and(&mut self, x: u16, y: u16) {
+    fn op_binary_and(&mut self, delta: &mut Delta, x: u8, y: u8) {
         let vx = self.general_registers[x as usize];
         let vy = self.general_registers[y as usize];
 
-        self.general_registers[x as usize] = vx & vy;
+        self.set_register(delta, x, vx & vy);
+        self.reset_flag_for_logic(delta);
     }
 
     // 8XY3: Logical XOR
     // VX is set to the bitwise/binary exclusive OR (XOR) of VX and VY.
-    fn op_logical_xor(&mut self, x: u16, y: u16) {
+    fn op_logical_xor(&mut self, delta: &mut Delta, x: u8, y: u8) {
         let vx = self.general_registers[x as usize];
         let vy = self.general_registers[y as usize];
 
-        self.general_registers[x as usize] = vx ^ vy;
+        self.set_register(delta, x, vx ^ vy);
+        self.reset_flag_for_logic(delta);
+    }
+
+    // The VIP's logic instructions left VF scrambled, which the logic quirk models as a reset
+    fn reset_flag_for_logic(&mut self, delta: &mut Delta) {
+        if self.quirks.logic {
+            self.set_register(delta, 0xF, 0);
+        }
     }
 
     // 8XY4: Add
     // VX is set to the value of VX + VY
     // Unlike 7XNN, the carry flag is affected. If the result is > 255, the flag register VF is set
     // to 1. Otherwise, it is set to 0.
-    fn op_add_with_carry(&mut self, x: u16, y: u16) {
+    fn op_add_with_carry(&mut self, delta: &mut Delta, x: u8, y: u8) {
         let vx = self.general_registers[x as usize];
         let vy = self.general_registers[y as usize];
 
         let (result, flag) = vx.overflowing_add(vy);
-        self.general_registers[x as usize] = result;
-        self.general_registers[0xF] = u8::from(flag);
+        self.set_with_flag(delta, x, result, u8::from(flag));
+    }
+
+    // Set VX to an arithmetic instruction's result and VF to its flag. VF is normally written
+    // last, so that with VF as VX it ends up holding the flag; the VF order quirk writes it
+    // first, so the result wins instead
+    fn set_with_flag(&mut self, delta: &mut Delta, x: u8, result: u8, flag: u8) {
+        if self.quirks.vf_order {
+            self.set_register(delta, 0xF, flag);
+            self.set_register(delta, x, result);
+        } else {
+            self.set_register(delta, x, result);
+            self.set_register(delta, 0xF, flag);
+        }
     }
 
     // 8XY5: Subtract (VX - VY into VX)
@@ -282,31 +425,19 @@ impl CPU {
     // (second operand) VF will be set to 1. If the subtrahend is larger, and we “underflow” the
     // result, VF is set to 0. Another way of thinking of it is that VF is set to 1 before the
     // subtraction, and then the subtraction either borrows from VF (setting it to 0) or not.
-    fn op_subtract_vy_from_vx(&mut self, x: u16, y: u16) {
+    fn op_subtract_vy_from_vx(&mut self, delta: &mut Delta, x: u8, y: u8) {
         let vx = self.general_registers[x as usize];
         let vy = self.general_registers[y as usize];
 
-        if vx > vy {
-            self.general_registers[0xF] = 1;
-        } else {
-            self.general_registers[0xF] = 0;
-        }
-
-        //self.general_registers[x as usize] = vx - vy;
-        self.general_registers[x as usize] = vx.wrapping_sub(vy);
+        self.set_with_flag(delta, x, vx.wrapping_sub(vy), u8::from(vx >= vy));
     }
+
     // 8XY7: Subtract (VY - VX into VX)
-    fn op_subtract_vx_from_vy(&mut self, x: u16, y: u16) {
+    fn op_subtract_vx_from_vy(&mut self, delta: &mut Delta, x: u8, y: u8) {
         let vx = self.general_registers[x as usize];
         let vy = self.general_registers[y as usize];
 
-        if vy > vx {
-            self.general_registers[0xF] = 1;
-        } else {
-            self.general_registers[0xF] = 0;
-        }
-
-        self.general_registers[x as usize] = vy - vx;
+        self.set_with_flag(delta, x, vy.wrapping_sub(vx), u8::from(vy >= vx));
     }
 
     // 8XY6 and 8XYE: Shift group
@@ -322,35 +453,42 @@ impl CPU {
     // 1. (Optional, or configurable) Set VX to the value of VY
     // 2. Shift the value of VX one bit to the right (8XY6) or left (8XYE)
     // 3. Set VF to 1 if the bit that was shifted out was 1, or 0 if it was 0
-    fn op_shift_right(&mut self, x: u16, _y: u16) {
-        let vx = self.general_registers[x as usize];
-        self.general_registers[x as usize] = vx >> 1;
+    fn op_shift_right(&mut self, delta: &mut Delta, x: u8, y: u8) {
+        let v = self.shift_operand(x, y);
+        self.set_with_flag(delta, x, v >> 1, v & 1);
     }
 
-    fn op_shift_left(&mut self, x: u16, _y: u16) {
-        let vx = self.general_registers[x as usize];
-        self.general_registers[x as usize] = vx << 1;
+    fn op_shift_left(&mut self, delta: &mut Delta, x: u8, y: u8) {
+        let v = self.shift_operand(x, y);
+        self.set_with_flag(delta, x, v << 1, v >> 7);
+    }
+
+    fn shift_operand(&self, x: u8, y: u8) -> u8 {
+        let from = if self.quirks.shift { x } else { y };
+        self.general_registers[from as usize]
     }
 
     // ANNN: Set Index
     // This sets the index register I to the value NNN.
-    fn op_set_index(&mut self, nnn: u16) {
-        self.index_register = nnn;
+    fn op_set_index(&mut self, delta: &mut Delta, nnn: u16) {
+        self.set_index(delta, nnn);
     }
 
     // BNNN - JP V0, addr
     // Jump to location nnn + V0.
-    fn op_jump_location_plus_reg(&mut self, nnn: u16) {
-        self.program_counter = (self.general_registers[0] as u16) + nnn;
+    // With the jump quirk, as on CHIP-48 and SCHIP, the register is VX, where X is NNN's top digit.
+    fn op_jump_location_plus_reg(&mut self, delta: &mut Delta, nnn: u16) {
+        let x = if self.quirks.jump { nnn >> 8 } else { 0 };
+        self.set_pc(delta, u16::from(self.general_registers[x as usize]) + nnn);
     }
 
     // CXKK - RND Vx, byte
     // Set Vx = random byte AND kk.
     // The interpreter generates a random number from 0 to 255, which is then ANDed with the value
     // kk. The results are stored in Vx. See instruction 8xy2 for more information on AND.
-    fn op_rand_and(&mut self, x: u16, nn: u8) {
+    fn op_rand_and(&mut self, delta: &mut Delta, x: u8, nn: u8) {
         let rand: u8 = self.rng.gen();
-        self.general_registers[x as usize] = rand & nn;
+        self.set_register(delta, x, rand & nn);
     }
 
     // DXYN: Display
@@ -363,23 +501,39 @@ impl CPU {
     // of the screen.
     fn op_display_vram(
         &mut self,
-        vram: &mut [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+        delta: &mut Delta,
+        vram: &mut Vram,
         ram: &RAM,
-        x: u16,
-        y: u16,
+        x: u8,
+        y: u8,
         n: u8,
-    ) {
-        self.general_registers[0xF] = 0;
+    ) -> Result<(), EmulationError> {
+        let mut collision = 0;
+        // The sprite's origin always wraps; with the clip quirk the rest of it doesn't
+        let left = self.general_registers[x as usize] as usize % DISPLAY_WIDTH;
+        let top = self.general_registers[y as usize] as usize % DISPLAY_HEIGHT;
         for byte in 0..n {
-            let y = (self.general_registers[y as usize] + byte) as usize % DISPLAY_HEIGHT;
+            let y = top + byte as usize;
+            if self.quirks.clip && y >= DISPLAY_HEIGHT {
+                break;
+            }
+            let y = y % DISPLAY_HEIGHT;
+            let row = ram.read(ram.offset(Address(self.index_register), u16::from(byte))?)?;
             for bit in 0..8 {
-                let x = (self.general_registers[x as usize] + bit) as usize % DISPLAY_WIDTH;
-                let fill =
-                    (ram.read_memory((self.index_register + byte as u16).into()) >> (7 - bit)) & 1;
-                self.general_registers[0xF] |= fill & vram[y][x];
-                vram[y][x] ^= fill;
+                let x = left + bit;
+                if self.quirks.clip && x >= DISPLAY_WIDTH {
+                    break;
+                }
+                let x = x % DISPLAY_WIDTH;
+                let fill = (row >> (7 - bit)) & 1;
+                collision |= fill & vram[y][x];
+                if fill == 1 {
+                    self.flip_pixel(delta, vram, x, y);
+                }
             }
         }
+        self.set_register(delta, 0xF, collision);
+        Ok(())
     }
 
     // Skip if key group
@@ -391,76 +545,57 @@ impl CPU {
     // EX9E: Skip if pressed
     // Will skip one instruction (increment PC by 2) if the key corresponding to the value in VX
     // is pressed.
-    fn op_skip_if_pressed<T>(&mut self, e: &Event<T>, x: u16) {
-        let queried_key = self
-            .lookup_key_code(self.general_registers[x as usize])
-            .unwrap();
-        //if event.key_pressed(queried_key) {
-        //    self.increment_pc();
-        //}
-
-        if let Event::DeviceEvent {
-            device_id: _,
-            event,
-        } = e
-        {
-            if let DeviceEvent::Key(keyboard_stuff) = event {
-                if let Some(virtual_key_code) = keyboard_stuff.virtual_keycode {
-                    if virtual_key_code == queried_key {
-                        self.increment_pc();
-                    }
-                }
-            }
+    fn op_skip_if_pressed(
+        &mut self,
+        delta: &mut Delta,
+        ram: &RAM,
+        keypad: &Keypad,
+        x: u8,
+    ) -> Result<(), EmulationError> {
+        if keypad.is_held(self.general_registers[x as usize]) {
+            self.increment_pc(delta, ram)?;
         }
+        Ok(())
     }
 
     /// EXA1: Skips if the key corresponding to the value in VX is not pressed.
-    fn op_skip_if_not_pressed<T>(&mut self, e: &Event<T>, x: u16) {
-        let queried_key = self
-            .lookup_key_code(self.general_registers[x as usize])
-            .unwrap();
-        //if !input.key_pressed(queried_key) {
-        //    self.increment_pc();
-        //}
-
-        if let Event::DeviceEvent {
-            device_id: _,
-            event,
-        } = e
-        {
-            if let DeviceEvent::Key(keyboard_stuff) = event {
-                if let Some(virtual_key_code) = keyboard_stuff.virtual_keycode {
-                    if virtual_key_code == queried_key {
-                        return;
-                    }
-                }
-            }
+    fn op_skip_if_not_pressed(
+        &mut self,
+        delta: &mut Delta,
+        ram: &RAM,
+        keypad: &Keypad,
+        x: u8,
+    ) -> Result<(), EmulationError> {
+        if !keypad.is_held(self.general_registers[x as usize]) {
+            self.increment_pc(delta, ram)?;
         }
-        self.increment_pc();
+        Ok(())
     }
 
     /// FX07: Sets VX to the current value of the delay timer
-    fn op_set_to_delay(&mut self, x: u16) {
-        self.general_registers[x as usize] = self.delay_timer;
+    fn op_set_to_delay(&mut self, delta: &mut Delta, x: u8) {
+        self.set_register(delta, x, self.delay_timer);
     }
 
     /// FX15: Sets delay timer to VX
-    fn op_set_delay_to(&mut self, x: u16) {
+    fn op_set_delay_to(&mut self, delta: &mut Delta, x: u8) {
+        delta.record(Change::DelayTimer(self.delay_timer));
         self.delay_timer = self.general_registers[x as usize];
     }
 
     /// FX18: Sets sound timer to VX
-    fn op_set_sound_to(&mut self, x: u16) {
+    fn op_set_sound_to(&mut self, delta: &mut Delta, x: u8) {
+        delta.record(Change::SoundTimer(self.sound_timer));
         self.sound_timer = self.general_registers[x as usize];
     }
 
     /// FX1E: Add to index
     /// Add VX to the index register I
-    fn op_add_to_index(&mut self, x: u16) {
+    fn op_add_to_index(&mut self, delta: &mut Delta, x: u8) {
         let tmp_i = self.index_register;
         let (result, flag) = tmp_i.overflowing_add(self.general_registers[x as usize].into());
-        self.general_registers[0xF] = u8::from(flag);
-        self.index_register = result;
+        self.set_register(delta, 0xF, u8::from(flag));
+        self.set_index(delta, result);
     }
 
     /// FX0A: Get key
@@ -477,20 +612,13 @@ impl CPU {
     /// If a key is pressed while this instruction is waiting for input, its hexadecimal value
     /// will be put in VX and execution continues. On the original COSMAC VIP, the key was only
     /// registered when it was pressed and then released.
-    fn op_get_key<T>(&mut self, e: &Event<T>, x: u16) {
-        match e {
-            Event::DeviceEvent {
-                device_id: _,
-                event,
-            } => match event {
-                DeviceEvent::Key(keyboard) => {
-                    self.general_registers[x as usize] = self
-                        .lookup_hex_of_key(keyboard.virtual_keycode.unwrap())
-                        .unwrap();
-                }
-                _ => self.decrement_pc(),
-            },
-            _ => self.decrement_pc(),
+    fn op_get_key(&mut self, delta: &mut Delta, keypad: &mut Keypad, x: u8) {
+        match keypad.take_pressed() {
+            Some(hex) => {
+                delta.record(Change::KeyPress(hex));
+                self.set_register(delta, x, hex);
+            }
+            None => self.decrement_pc(delta),
         }
     }
 
@@ -502,8 +630,9 @@ impl CPU {
     /// An 8-bit register can hold two hexadecimal numbers, but this would only point to one
     /// character. The original COSMAC VIP interpreter just took the last nibble of VX and used
     /// that as the character.
-    fn op_font_character(&mut self, x: u16) {
-        self.index_register = u16::from(self.general_registers[x as usize] * 5); // ??
+    fn op_font_character(&mut self, delta: &mut Delta, ram: &RAM, x: u8) {
+        let addr = ram.font_address(self.general_registers[x as usize]);
+        self.set_index(delta, addr);
     }
 
     /// FX33: Binary-coded decimal conversion
@@ -512,17 +641,20 @@ impl CPU {
     /// digits in memory at the address in the index register I. For example, if VX contains 156
     /// (or 9C in hexadecimal), it would put the number 1 at the address in I, 5 in address I + 1,
     /// and 6 in address I + 2.
-    fn op_coded_dec_conv(&mut self, ram: &mut RAM, x: u16) {
-        let mut vx = self.general_registers[x as usize];
-        let mut digit = 0;
-        let base = 10;
-
-        while vx != 0 {
-            ram.set_memory(vx % base, (self.index_register + digit) as usize)
-                .unwrap();
-            vx /= base;
-            digit += 1;
+    fn op_coded_dec_conv(
+        &mut self,
+        delta: &mut Delta,
+        ram: &mut RAM,
+        x: u8,
+    ) -> Result<(), EmulationError> {
+        let vx = self.general_registers[x as usize];
+        // Always all three digits, leading zeros included
+        let digits = [vx / 100, vx / 10 % 10, vx % 10];
+        for (n, digit) in (0..).zip(digits) {
+            let addr = ram.offset(Address(self.index_register), n)?;
+            self.write_memory(delta, ram, addr, digit)?;
         }
+        Ok(())
     }
 
     // These two instructions store registers to memory, or load them from memory, respectively.
@@ -532,23 +664,233 @@ impl CPU {
     /// V0) will be stored in successive memory addresses, starting with the one that’s stored in
     /// I. V0 will be stored at the address in I, V1 will be stored in I + 1, and so on, until
     /// VX is stored in I + X.
-    fn op_store_memory(&mut self, ram: &mut RAM, x: u16) {
-        for vn in 0..x {
-            ram.set_memory(
-                self.general_registers[vn as usize],
-                (self.index_register + vn) as usize,
-            )
-            .unwrap();
+    fn op_store_memory(
+        &mut self,
+        delta: &mut Delta,
+        ram: &mut RAM,
+        x: u8,
+    ) -> Result<(), EmulationError> {
+        for vn in 0..=x {
+            let addr = ram.offset(Address(self.index_register), u16::from(vn))?;
+            self.write_memory(delta, ram, addr, self.general_registers[vn as usize])?;
         }
+        self.advance_index_for_load_store(delta, x);
+        Ok(())
     }
 
     /// FX65: Load memory
     /// FX65 does the same thing, except that it takes the value stored at the memory addresses
     /// and loads them into the variable registers instead.
-    fn op_load_memory(&mut self, ram: &RAM, x: u16) {
-        for vn in 0..x {
-            self.general_registers[vn as usize] =
-                ram.read_memory((self.index_register + vn) as usize);
+    fn op_load_memory(
+        &mut self,
+        delta: &mut Delta,
+        ram: &RAM,
+        x: u8,
+    ) -> Result<(), EmulationError> {
+        for vn in 0..=x {
+            let val = ram.read(ram.offset(Address(self.index_register), u16::from(vn))?)?;
+            self.set_register(delta, vn, val);
+        }
+        self.advance_index_for_load_store(delta, x);
+        Ok(())
+    }
+
+    // The VIP left I pointing just past the last register stored or loaded
+    fn advance_index_for_load_store(&mut self, delta: &mut Delta, x: u8) {
+        if !self.quirks.load_store {
+            let i = self.index_register.wrapping_add(u16::from(x) + 1);
+            self.set_index(delta, i);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delta::Delta;
+    use crate::error::EmulationError;
+    use crate::machine::Machine;
+    use crate::quirks::Quirks;
+    use crate::ram::{Address, MemoryConfig, MemoryPolicy, PROG_MEM_START, RAM};
+
+    // Run a program from the start of program memory, an instruction for each opcode
+    fn run(program: &[u16]) -> Machine {
+        run_with(Quirks::default(), program)
+    }
+
+    fn run_with(quirks: Quirks, program: &[u16]) -> Machine {
+        let mut machine = Machine::new();
+        machine.cpu.quirks = quirks;
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        machine
+            .ram
+            .load_rom_bytes(&rom, PROG_MEM_START as u16)
+            .unwrap();
+        for _ in program {
+            machine.step().unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn subtraction_wraps_and_flags_no_borrow() {
+        // (VX, VY, 8XY5's VX and VF, 8XY7's VX and VF)
+        let cases = [
+            (5, 3, 2, 1, 254, 0),
+            (3, 5, 254, 0, 2, 1),
+            (4, 4, 0, 1, 0, 1),
+            (0, 255, 1, 0, 255, 1),
+        ];
+        for (vx, vy, sub, sub_flag, subn, subn_flag) in cases {
+            let machine = run(&[0x6000 | vx, 0x6100 | vy, 0x8015]);
+            let v = machine.cpu.general_registers;
+            assert_eq!((v[0], v[0xF]), (sub, sub_flag), "{} - {}", vx, vy);
+
+            let machine = run(&[0x6000 | vx, 0x6100 | vy, 0x8017]);
+            let v = machine.cpu.general_registers;
+            assert_eq!((v[0], v[0xF]), (subn, subn_flag), "{} - {}", vy, vx);
+        }
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_target() {
+        // VF = 3 and V1 = 5, then each instruction with VF as VX
+        let cases = [
+            (0x8F15, 0), // 3 - 5 borrows
+            (0x8F17, 1), // 5 - 3 doesn't
+            (0x8F14, 0), // 3 + 5 doesn't carry
+            (0x8F16, 1), // the bit shifted out of 3 or 5 is 1
+            (0x8F1E, 0), // and so is the top bit, 0
+        ];
+        for (opcode, flag) in cases {
+            let machine = run(&[0x6F03, 0x6105, opcode]);
+            assert_eq!(machine.cpu.general_registers[0xF], flag, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn result_wins_with_the_vf_order_quirk() {
+        let quirks = Quirks {
+            vf_order: true,
+            ..Quirks::default()
+        };
+        // VF = 3 and V1 = 5, then each instruction with VF as VX
+        let cases = [
+            (0x8F15, 254),
+            (0x8F17, 2),
+            (0x8F14, 8),
+            (0x8F16, 1),
+            (0x8F1E, 6),
+        ];
+        for (opcode, result) in cases {
+            let machine = run_with(quirks, &[0x6F03, 0x6105, opcode]);
+            assert_eq!(machine.cpu.general_registers[0xF], result, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn loads_past_the_top_of_the_address_space_follow_the_memory_policy() {
+        // FX65 reading V0 and V1 from 0xFFFF, so V1 comes from one past the top
+        let mut machine = Machine::new();
+        machine.ram.load_rom_bytes(&[0xF1, 0x65], 0x200).unwrap();
+        machine.cpu.index_register = 0xFFFF;
+        assert_eq!(
+            machine.step().unwrap_err(),
+            EmulationError::MemoryOutOfBounds(Address(0xFFFF))
+        );
+
+        machine.ram = RAM::new(MemoryConfig {
+            policy: MemoryPolicy::Wrap,
+            ..MemoryConfig::default()
+        });
+        machine.ram.load_rom_bytes(&[0xF1, 0x65], 0x200).unwrap();
+        machine.cpu.program_counter = 0x200;
+        machine.step().unwrap();
+        // The top of memory, then the bottom, where the font starts
+        let v = machine.cpu.general_registers;
+        assert_eq!((v[0], v[1]), (0, 0xF0));
+    }
+
+    #[test]
+    fn undoing_fx0a_gives_its_key_press_back() {
+        let mut machine = Machine::new();
+        machine.ram.load_rom_bytes(&[0xF3, 0x0A], 0x200).unwrap();
+        machine.keypad.set_held(5, true);
+        let delta = machine.step().unwrap();
+        assert_eq!(machine.cpu.general_registers[3], 5);
+
+        machine.undo(&delta);
+        assert_eq!(machine.cpu.program_counter, 0x200);
+        assert_eq!(machine.keypad.take_pressed(), Some(5));
+    }
+
+    // Run one instruction with I at 300, after filling 300 to 303 with `memory`
+    fn run_at_300(program: &[u16], memory: [u8; 4]) -> Machine {
+        let mut machine = Machine::new();
+        for (n, byte) in (0..).zip(memory) {
+            machine.ram.write(Address(0x300 + n), byte).unwrap();
+        }
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        machine
+            .ram
+            .load_rom_bytes(&rom, PROG_MEM_START as u16)
+            .unwrap();
+        for _ in program {
+            machine.step().unwrap();
+        }
+        machine
+    }
+
+    fn memory_at_300(machine: &Machine) -> &[u8] {
+        &machine.ram.bytes()[0x300..0x304]
+    }
+
+    #[test]
+    fn bcd_writes_every_digit_hundreds_first() {
+        let cases = [
+            (156, [1, 5, 6]),
+            (7, [0, 0, 7]),
+            (40, [0, 4, 0]),
+            (255, [2, 5, 5]),
+        ];
+        for (value, digits) in cases {
+            let machine = run_at_300(&[0x6000 | value, 0xA300, 0xF033], [9; 4]);
+            assert_eq!(memory_at_300(&machine)[..3], digits, "{}", value);
+            assert_eq!(memory_at_300(&machine)[3], 9, "{}", value);
+        }
+    }
+
+    #[test]
+    fn load_and_store_include_vx() {
+        let program = [0x6001, 0x6102, 0x6203, 0x6304, 0xA300, 0xF255];
+        let machine = run_at_300(&program, [9; 4]);
+        assert_eq!(memory_at_300(&machine), [1, 2, 3, 9]);
+
+        let machine = run_at_300(&[0x6304, 0xA300, 0xF265], [5, 6, 7, 8]);
+        assert_eq!(machine.cpu.general_registers[..4], [5, 6, 7, 4]);
+    }
+
+    #[test]
+    fn the_pc_runs_off_the_top_of_memory_by_the_memory_policy() {
+        // A skip taken at FFFC would go past the top of the address space
+        let mut machine = Machine::new();
+        machine.cpu.program_counter = 0xFFFC;
+        let mut delta = Delta::new();
+        machine.cpu.increment_pc(&mut delta, &machine.ram).unwrap();
+        assert_eq!(
+            machine.cpu.increment_pc(&mut delta, &machine.ram),
+            Err(EmulationError::MemoryOutOfBounds(Address(0xFFFE)))
+        );
+
+        // Wrapping, a skip from the last instruction in memory lands at the bottom
+        machine.ram = RAM::new(MemoryConfig {
+            policy: MemoryPolicy::Wrap,
+            ..MemoryConfig::default()
+        });
+        machine.ram.load_rom_bytes(&[0x30, 0x00], 0x200).unwrap();
+        machine.ram.write(Address(0xFFC), 0x30).unwrap();
+        machine.ram.write(Address(0xFFD), 0x00).unwrap();
+        machine.cpu.program_counter = 0xFFC;
+        machine.step().unwrap();
+        assert_eq!(machine.cpu.program_counter, 0);
+    }
+}
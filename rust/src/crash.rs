@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::EmulationError;
+use crate::machine::Machine;
+use crate::ram::MEM_BYTES;
+use crate::snapshot;
+use crate::trace::ALL_COLUMNS;
+
+// How many bytes either side of PC and I to include in the memory dumps
+const DUMP_RADIUS: usize = 32;
+
+/// Write a crash report for `error` into the current directory, along with a snapshot of the
+/// machine that can be loaded with `--snapshot` to reproduce it. Returns the report's path.
+pub fn write_report(machine: &Machine, error: &EmulationError) -> io::Result<PathBuf> {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let report_path = PathBuf::from(format!("chip-8-crash-{}.txt", stamp));
+    let snapshot_path = PathBuf::from(format!("chip-8-crash-{}.snapshot", stamp));
+
+    let mut snapshot = BufWriter::new(File::create(&snapshot_path)?);
+    snapshot::save(machine, &mut snapshot)?;
+    snapshot.flush()?;
+
+    let mut out = BufWriter::new(File::create(&report_path)?);
+    let cpu = &machine.cpu;
+    writeln!(out, "CHIP-8 crash report")?;
+    let symbols = &machine.symbols;
+    writeln!(
+        out,
+        "Error: {} at {}",
+        error,
+        symbols.describe(cpu.program_counter)
+    )?;
+    writeln!(out, "Snapshot: {}", snapshot_path.display())?;
+
+    writeln!(out, "\nLast executed instructions (oldest first):")?;
+    for entry in machine.recent() {
+        writeln!(out, "  {}", entry.format(&ALL_COLUMNS, symbols))?;
+    }
+
+    writeln!(out, "\nRegisters:")?;
+    writeln!(
+        out,
+        "  PC={:04X} I={:04X} SP={:02X} DT={:02X} ST={:02X}",
+        cpu.program_counter,
+        cpu.index_register,
+        machine.stack.stack_pointer(),
+        cpu.delay_timer,
+        cpu.sound_timer
+    )?;
+    let regs: Vec<String> = cpu
+        .general_registers
+        .iter()
+        .enumerate()
+        .map(|(n, v)| format!("V{:X}={:02X}", n, v))
+        .collect();
+    writeln!(out, "  {}", regs.join(" "))?;
+
+    writeln!(out, "\nBacktrace (innermost first):")?;
+    for line in machine.backtrace() {
+        writeln!(out, "  {}", line)?;
+    }
+
+    writeln!(out, "\nMemory around PC:")?;
+    hex_dump(&mut out, machine, cpu.program_counter as usize)?;
+    writeln!(out, "\nMemory around I:")?;
+    hex_dump(&mut out, machine, cpu.index_register as usize)?;
+
+    out.flush()?;
+    Ok(report_path)
+}
+
+/// Dump whole 16-byte rows around `center`, with the byte at `center` in brackets.
+fn hex_dump(out: &mut impl Write, machine: &Machine, center: usize) -> io::Result<()> {
+    if center >= MEM_BYTES {
+        return writeln!(out, "  {:#X} is outside of memory", center);
+    }
+    let start = center.saturating_sub(DUMP_RADIUS) & !0xF;
+    let end = (center + DUMP_RADIUS).min(MEM_BYTES - 1);
+    for row in (start..=end).step_by(16) {
+        let mut line = format!("  {:03X}:", row);
+        for addr in row..row + 16 {
+            let byte = machine.ram.bytes()[addr];
+            if addr == center {
+                line += &format!("[{:02X}]", byte);
+            } else {
+                line += &format!(" {:02X} ", byte);
+            }
+        }
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
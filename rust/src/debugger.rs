@@ -0,0 +1,404 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, BufRead, Write};
+
+use crate::cli::parse_hex;
+use crate::crash;
+use crate::delta::Delta;
+use crate::error::EmulationError;
+use crate::machine::Machine;
+use crate::ram::{Access, Address};
+
+// How many instructions of undo history to keep before the oldest are dropped
+const HISTORY_LIMIT: usize = 100_000;
+// There's no way to interrupt a run from the prompt, so give up after this many instructions
+const CONTINUE_LIMIT: usize = 1_000_000;
+
+// Addresses are 16 bits, so `mem` stops at the top of them
+const ADDRESS_SPACE: usize = 0x10000;
+
+const HELP: &str = "\
+commands (addresses are hex, or labels like draw_player+0x4 when symbols are loaded):
+  s,  step [n]           execute n instructions (default 1)
+  sb, step-back [n]      undo n instructions (default 1)
+  c,  continue           run until a breakpoint or watchpoint is hit
+  rc, reverse-continue   run backwards until a breakpoint or watchpoint is hit
+  b,  break <addr>       set a breakpoint on the PC reaching addr
+  d,  delete <addr>      remove a breakpoint
+  w,  watch <addr>       stop whenever the memory byte at addr is written
+      unwatch <addr>     remove a watchpoint
+  r,  regs               show the registers
+  bt, backtrace          show the call stack
+  x,  mem <addr> [len]   dump len bytes of memory (default 16)
+      map                show the memory map
+      screen             draw the display as text
+  q,  quit";
+
+/// Why a `continue` or `reverse-continue` stopped.
+enum Stop {
+    Breakpoint(u16),
+    Watchpoint(usize),
+    Recursion(u16),
+    Fault(EmulationError),
+    HistoryExhausted,
+    Limit,
+}
+
+/// A headless, prompt-driven debugger that keeps an undo delta for every instruction it runs,
+/// so execution can be stepped backwards as well as forwards.
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<usize>,
+    history: VecDeque<Delta>,
+    // The timers count down once every this many instructions, as a frame's worth, and this
+    // many have run since they last did
+    ticks_per_frame: usize,
+    ticks: usize,
+}
+
+impl Debugger {
+    /// Wrap a machine, starting with any breakpoints its symbols define.
+    pub fn new(machine: Machine, ticks_per_frame: usize) -> Debugger {
+        Debugger {
+            breakpoints: machine.symbols.breakpoints().collect(),
+            machine,
+            watchpoints: BTreeSet::new(),
+            history: VecDeque::new(),
+            ticks_per_frame,
+            ticks: 0,
+        }
+    }
+
+    /// Read commands from stdin until `quit` or end of input.
+    pub fn run(mut self) {
+        let stdin = io::stdin();
+        self.print_location();
+        loop {
+            print!("(chip-8) ");
+            // A closed or broken terminal is the same as the end of input
+            if io::stdout().flush().is_err() {
+                break;
+            }
+            let mut line = String::new();
+            if !matches!(stdin.lock().read_line(&mut line), Ok(n) if n > 0) {
+                break;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => continue,
+                ["q" | "quit"] => break,
+                ["h" | "help"] => println!("{}", HELP),
+                ["s" | "step", rest @ ..] => self.cmd_step(rest),
+                ["sb" | "step-back", rest @ ..] => self.cmd_step_back(rest),
+                ["c" | "continue"] => {
+                    let stop = self.continue_forward();
+                    self.report(stop);
+                }
+                ["rc" | "reverse-continue"] => {
+                    let stop = self.reverse_continue();
+                    self.report(stop);
+                }
+                ["b" | "break", addr] => match self.parse_location(addr) {
+                    Some(addr) => {
+                        self.breakpoints.insert(addr);
+                    }
+                    None => println!("Invalid address {}", addr),
+                },
+                ["d" | "delete", addr] => match self.parse_location(addr) {
+                    Some(addr) => {
+                        self.breakpoints.remove(&addr);
+                    }
+                    None => println!("Invalid address {}", addr),
+                },
+                ["w" | "watch", addr] => match self.parse_location(addr) {
+                    Some(addr) => {
+                        self.watchpoints.insert(addr as usize);
+                    }
+                    None => println!("Invalid address {}", addr),
+                },
+                ["unwatch", addr] => match self.parse_location(addr) {
+                    Some(addr) => {
+                        self.watchpoints.remove(&(addr as usize));
+                    }
+                    None => println!("Invalid address {}", addr),
+                },
+                ["r" | "regs"] => self.print_registers(),
+                ["bt" | "backtrace"] => {
+                    for line in self.machine.backtrace() {
+                        println!("{}", line);
+                    }
+                }
+                ["x" | "mem", addr, rest @ ..] => self.cmd_mem(addr, rest),
+                ["map"] => self.print_memory_map(),
+                ["screen"] => self.print_screen(),
+                _ => println!("Unknown command. Type `help` for a list of commands."),
+            }
+        }
+    }
+
+    /// Execute one instruction, returning why execution should stop, if it should.
+    fn step(&mut self) -> Option<Stop> {
+        let mut delta = match self.machine.step() {
+            Ok(delta) => delta,
+            Err(e) => return Some(Stop::Fault(e)),
+        };
+        // The last instruction of a frame ends it, so its delta undoes the timers too
+        self.ticks += 1;
+        if self.ticks == self.ticks_per_frame {
+            self.ticks = 0;
+            self.machine.cpu.tick_timers_undoably(&mut delta);
+        }
+        let stop = match self.watched_write(&delta) {
+            Some(addr) => Some(Stop::Watchpoint(addr)),
+            None => self.machine.runaway_recursion(&delta).map(Stop::Recursion),
+        };
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        self.history.push_back(delta);
+        stop
+    }
+
+    /// Undo the most recent instruction, returning its delta, or `None` if there's no history
+    /// left to undo.
+    fn step_back(&mut self) -> Option<Delta> {
+        let delta = self.history.pop_back()?;
+        self.machine.undo(&delta);
+        self.ticks = self
+            .ticks
+            .checked_sub(1)
+            .unwrap_or(self.ticks_per_frame - 1);
+        Some(delta)
+    }
+
+    fn continue_forward(&mut self) -> Stop {
+        for _ in 0..CONTINUE_LIMIT {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+            let pc = self.machine.cpu.program_counter;
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+        Stop::Limit
+    }
+
+    fn reverse_continue(&mut self) -> Stop {
+        while let Some(delta) = self.step_back() {
+            if let Some(addr) = self.watched_write(&delta) {
+                return Stop::Watchpoint(addr);
+            }
+            let pc = self.machine.cpu.program_counter;
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+        Stop::HistoryExhausted
+    }
+
+    fn watched_write(&self, delta: &Delta) -> Option<usize> {
+        self.watchpoints
+            .iter()
+            .copied()
+            .find(|addr| delta.wrote_memory(*addr))
+    }
+
+    fn cmd_step(&mut self, args: &[&str]) {
+        let Some(count) = parse_count(args) else {
+            println!("Invalid count");
+            return;
+        };
+        for _ in 0..count {
+            if let Some(stop) = self.step() {
+                self.report(stop);
+                return;
+            }
+        }
+        if count == 1 {
+            if let Some(delta) = self.history.back() {
+                for change in delta.changes() {
+                    println!("  {}", change);
+                }
+            }
+        }
+        self.print_location();
+    }
+
+    fn cmd_step_back(&mut self, args: &[&str]) {
+        let Some(count) = parse_count(args) else {
+            println!("Invalid count");
+            return;
+        };
+        for _ in 0..count {
+            if self.step_back().is_none() {
+                self.report(Stop::HistoryExhausted);
+                return;
+            }
+        }
+        self.print_location();
+    }
+
+    fn cmd_mem(&self, addr: &str, args: &[&str]) {
+        let (Some(start), Some(len)) = (
+            self.parse_location(addr).map(usize::from),
+            args.first().map_or(Some(16), |n| n.parse::<usize>().ok()),
+        ) else {
+            println!("Usage: mem <addr> [len]");
+            return;
+        };
+        let end = start.saturating_add(len).min(ADDRESS_SPACE);
+        for row in (start..end).step_by(16) {
+            let bytes: Vec<String> = (row..(row + 16).min(end))
+                .map(|a| match self.machine.ram.bytes().get(a) {
+                    Some(byte) => format!("{:02X}", byte),
+                    None => "--".to_string(),
+                })
+                .collect();
+            println!("{:03X}: {}", row, bytes.join(" "));
+        }
+    }
+
+    fn report(&self, stop: Stop) {
+        match stop {
+            Stop::Breakpoint(addr) => println!("Breakpoint at {}", self.describe(addr)),
+            Stop::Watchpoint(addr) => println!(
+                "Watchpoint: memory at {} written",
+                self.describe(addr as u16)
+            ),
+            Stop::Recursion(callee) => println!(
+                "Runaway recursion into {}, {} of {} stack entries used",
+                self.describe(callee),
+                self.machine.stack.depth(),
+                self.machine.stack.capacity()
+            ),
+            Stop::Fault(e) => {
+                println!("Fault: {}", e);
+                match crash::write_report(&self.machine, &e) {
+                    Ok(path) => println!("Crash report written to {}", path.display()),
+                    Err(e) => println!("Could not write crash report: {}", e),
+                }
+            }
+            Stop::HistoryExhausted => println!("Reached the start of recorded history"),
+            Stop::Limit => println!("Stopped after {} instructions", CONTINUE_LIMIT),
+        }
+        self.print_location();
+    }
+
+    fn print_location(&self) {
+        let pc = self.machine.cpu.program_counter;
+        let opcode = match self.machine.ram.read_word(Address(pc)) {
+            Ok(opcode) => format!("{:04X}", opcode),
+            Err(_) => "----".to_string(),
+        };
+        match self.machine.symbols.source(pc) {
+            Some((file, line)) => {
+                println!("{}: {}  ({}:{})", self.describe(pc), opcode, file, line)
+            }
+            None => println!("{}: {}", self.describe(pc), opcode),
+        }
+    }
+
+    fn describe(&self, addr: u16) -> String {
+        self.machine.symbols.describe(addr)
+    }
+
+    /// Parse a hex address, a label, or a label plus a hex offset.
+    fn parse_location(&self, s: &str) -> Option<u16> {
+        let (name, offset) = match s.split_once('+') {
+            Some((name, offset)) => (name, parse_hex(offset)?),
+            None => (s, 0),
+        };
+        let base = match self.machine.symbols.address_of(name) {
+            Some(addr) => addr as usize,
+            None if offset == 0 => parse_hex(name)?,
+            None => return None,
+        };
+        u16::try_from(base + offset).ok()
+    }
+
+    fn print_registers(&self) {
+        let cpu = &self.machine.cpu;
+        println!(
+            "PC {:#05X}  I {:#05X}  DT {:02X}  ST {:02X}",
+            cpu.program_counter, cpu.index_register, cpu.delay_timer, cpu.sound_timer
+        );
+        let regs: Vec<String> = cpu
+            .general_registers
+            .iter()
+            .enumerate()
+            .map(|(n, v)| format!("V{:X} {:02X}", n, v))
+            .collect();
+        println!("{}", regs.join("  "));
+    }
+
+    fn print_memory_map(&self) {
+        let mut regions = self.machine.ram.regions().to_vec();
+        regions.sort_by_key(|r| (r.start, std::cmp::Reverse(r.end)));
+        for r in regions {
+            let access = match r.access {
+                Access::ReadOnly => "read-only",
+                Access::ReadWrite => "read-write",
+            };
+            println!(
+                "{:#05X}-{:#05X}  {:<12} {}",
+                r.start,
+                r.end - 1,
+                r.name,
+                access
+            );
+        }
+    }
+
+    fn print_screen(&self) {
+        for row in self.machine.vram.iter() {
+            let line: String = row
+                .iter()
+                .map(|&pix| if pix == 0 { '.' } else { '#' })
+                .collect();
+            println!("{}", line);
+        }
+    }
+}
+
+fn parse_count(args: &[&str]) -> Option<usize> {
+    match args {
+        [] => Some(1),
+        [n] => n.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ram::PROG_MEM_START;
+
+    #[test]
+    fn timers_count_down_and_back_up() {
+        // Set DT to 3, wait for it to run out, then stop at 20A
+        let program: [u16; 6] = [0x6003, 0xF015, 0xF007, 0x3000, 0x1204, 0x120A];
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut machine = Machine::new();
+        machine
+            .ram
+            .load_rom_bytes(&rom, PROG_MEM_START as u16)
+            .unwrap();
+        let mut debugger = Debugger::new(machine, 3);
+        debugger.breakpoints.insert(0x20A);
+
+        assert!(matches!(
+            debugger.continue_forward(),
+            Stop::Breakpoint(0x20A)
+        ));
+        assert_eq!(debugger.machine.cpu.delay_timer, 0);
+
+        // Back to just after F015, with DT as it set it
+        for _ in 2..debugger.history.len() {
+            debugger.step_back();
+        }
+        assert_eq!(debugger.machine.cpu.program_counter, 0x204);
+        assert_eq!(debugger.machine.cpu.delay_timer, 3);
+        assert_eq!(debugger.ticks, 2);
+    }
+}
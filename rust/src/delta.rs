@@ -0,0 +1,96 @@
+use std::fmt;
+
+use crate::{cpu::CPU, keypad::Keypad, ram::Address, ram::RAM, stack::Stack, Vram};
+
+/// A single piece of machine state that an instruction overwrote, holding enough of the old
+/// value to put it back.
+#[derive(Clone, Copy, Debug)]
+pub enum Change {
+    Register { x: usize, old: u8 },
+    Index(u16),
+    ProgramCounter(u16),
+    DelayTimer(u8),
+    SoundTimer(u8),
+    // The address as resolved by the memory policy, so always inside memory
+    Memory { addr: usize, old: u8 },
+    // Pixels only ever flip, so flipping them again undoes the change
+    Pixel { x: usize, y: usize },
+    StackPointer(usize),
+    StackEntry { slot: usize, old: u16 },
+    // A key press that FX0A took, so undoing it leaves the press waiting again
+    KeyPress(u8),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Register { x, old } => write!(f, "V{:X} (was {:02X})", x, old),
+            Change::Index(old) => write!(f, "I (was {:#05X})", old),
+            Change::ProgramCounter(old) => write!(f, "PC (was {:#05X})", old),
+            Change::DelayTimer(old) => write!(f, "DT (was {:02X})", old),
+            Change::SoundTimer(old) => write!(f, "ST (was {:02X})", old),
+            Change::Memory { addr, old } => write!(f, "[{:#05X}] (was {:02X})", addr, old),
+            Change::Pixel { x, y } => write!(f, "pixel ({}, {}) flipped", x, y),
+            Change::StackPointer(old) => write!(f, "SP (was {:02X})", old),
+            Change::StackEntry { slot, old } => write!(f, "stack[{}] (was {:#05X})", slot, old),
+            Change::KeyPress(hex) => write!(f, "key {:X} press taken", hex),
+        }
+    }
+}
+
+/// Everything one instruction changed, in the order it changed it.
+#[derive(Clone, Debug, Default)]
+pub struct Delta {
+    changes: Vec<Change>,
+}
+
+impl Delta {
+    pub fn new() -> Delta {
+        Delta {
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Whether this instruction wrote to the memory byte at `addr`.
+    pub fn wrote_memory(&self, addr: usize) -> bool {
+        self.changes
+            .iter()
+            .any(|c| matches!(c, Change::Memory { addr: a, .. } if *a == addr))
+    }
+
+    /// Put the machine back into the state it was in before the instruction ran. Changes are
+    /// undone newest first, so a register written twice ends up with its original value.
+    pub fn revert(
+        &self,
+        cpu: &mut CPU,
+        keypad: &mut Keypad,
+        stack: &mut Stack,
+        vram: &mut Vram,
+        ram: &mut RAM,
+    ) {
+        for change in self.changes.iter().rev() {
+            match *change {
+                Change::Register { x, old } => cpu.general_registers[x] = old,
+                Change::Index(old) => cpu.index_register = old,
+                Change::ProgramCounter(old) => cpu.program_counter = old,
+                Change::DelayTimer(old) => cpu.delay_timer = old,
+                Change::SoundTimer(old) => cpu.sound_timer = old,
+                Change::Memory { addr, old } => ram
+                    .write(Address(addr as u16), old)
+                    .expect("undo history refers to memory that doesn't exist"),
+                Change::Pixel { x, y } => vram[y][x] ^= 1,
+                Change::StackPointer(old) => stack.restore_pointer(old),
+                Change::StackEntry { slot, old } => stack.restore_entry(slot, old),
+                Change::KeyPress(hex) => keypad.restore_pressed(hex),
+            }
+        }
+    }
+}
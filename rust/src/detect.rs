@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::quirks::{Platform, Quirks};
+
+// A walk that reaches fewer instructions than this hasn't seen enough of the ROM to go on
+const MIN_INSTRUCTIONS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// A guess at what a ROM was written for, from the instructions it uses.
+pub struct Guess {
+    pub platform: Platform,
+    pub confidence: Confidence,
+    // Why the platform was picked, one line each
+    pub reasons: Vec<String>,
+    // Addresses of 0NNN calls into VIP machine code, which this emulator can't run
+    pub machine_calls: Vec<u16>,
+    // Quirk names and settings the code seems to rely on, with why
+    pub quirks: Vec<(&'static str, bool, String)>,
+}
+
+impl Guess {
+    /// The platform's quirks, with any the code seems to rely on applied.
+    pub fn profile(&self) -> Quirks {
+        let mut quirks = self.platform.quirks();
+        for &(name, on, _) in &self.quirks {
+            quirks
+                .set(name, on)
+                .expect("detection only suggests known quirks");
+        }
+        quirks
+    }
+}
+
+/// An extension to CHIP-8 that an opcode belongs to.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Extension {
+    Schip,
+    XoChip,
+}
+
+/// Guess the platform of a ROM loaded at `base` by following its code from the entry point, so
+/// that data which happens to look like an instruction isn't counted.
+pub fn detect(rom: &[u8], base: u16) -> Guess {
+    let code = walk(rom, base);
+
+    // Each extension, with the distinct opcode forms seen and how many times
+    let mut evidence: BTreeMap<Extension, BTreeMap<&'static str, usize>> = BTreeMap::new();
+    let mut machine_calls = Vec::new();
+    for (&addr, &opcode) in &code {
+        if let Some((extension, form)) = extension_of(opcode) {
+            *evidence
+                .entry(extension)
+                .or_default()
+                .entry(form)
+                .or_default() += 1;
+        } else if let Some(Instruction::MachineCall { .. }) = Instruction::decode(opcode) {
+            machine_calls.push(addr);
+        }
+    }
+
+    // XO-CHIP is a superset of SCHIP, so any sign of it wins
+    let (platform, forms) = match (
+        evidence.get(&Extension::XoChip),
+        evidence.get(&Extension::Schip),
+    ) {
+        (Some(forms), _) => (Platform::XoChip, Some(forms)),
+        (None, Some(forms)) => (Platform::Schip, Some(forms)),
+        (None, None) => (Platform::Chip8, None),
+    };
+
+    let mut reasons = Vec::new();
+    let confidence = match forms {
+        Some(forms) => {
+            let uses: usize = forms.values().sum();
+            for (form, count) in forms {
+                reasons.push(format!("uses {} {}", form, times(*count)));
+            }
+            if forms.len() >= 2 || uses >= 3 {
+                Confidence::High
+            } else {
+                Confidence::Medium
+            }
+        }
+        None if code.len() < MIN_INSTRUCTIONS => {
+            reasons.push(format!(
+                "only {} instructions could be followed",
+                code.len()
+            ));
+            Confidence::Low
+        }
+        None => {
+            reasons.push(format!(
+                "none of the {} instructions followed are SCHIP or XO-CHIP ones",
+                code.len()
+            ));
+            Confidence::Medium
+        }
+    };
+    if !machine_calls.is_empty() {
+        reasons.push(format!(
+            "calls VIP machine code {}, so it's a hybrid ROM",
+            times(machine_calls.len())
+        ));
+    }
+
+    Guess {
+        platform,
+        confidence,
+        reasons,
+        machine_calls,
+        quirks: quirk_hints(&code),
+    }
+}
+
+/// The opcodes reachable from `base`, by address. Unlike the disassembler's walk, this knows
+/// the SCHIP and XO-CHIP instructions, so it carries on past them.
+fn walk(rom: &[u8], base: u16) -> BTreeMap<u16, u16> {
+    let end = base as usize + rom.len();
+    let mut code = BTreeMap::new();
+    let mut pending = vec![base];
+
+    while let Some(addr) = pending.pop() {
+        if (addr as usize) < base as usize || addr as usize + 2 > end || code.contains_key(&addr) {
+            continue;
+        }
+        let offset = (addr - base) as usize;
+        let opcode = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+        // Both skips past, and execution after, F000 NNNN's second word land 4 bytes on
+        let size = if opcode == 0xF000 { 4 } else { 2 };
+
+        match Instruction::decode(opcode) {
+            Some(Instruction::Jump { nnn }) | Some(Instruction::JumpPlusV0 { nnn }) => {
+                pending.push(nnn)
+            }
+            Some(Instruction::Return) => {}
+            Some(Instruction::Call { nnn }) => {
+                pending.push(nnn);
+                pending.push(addr + 2);
+            }
+            Some(
+                Instruction::SkipIfEq { .. }
+                | Instruction::SkipIfNotEq { .. }
+                | Instruction::SkipIfEqReg { .. }
+                | Instruction::SkipIfNotEqReg { .. }
+                | Instruction::SkipIfPressed { .. }
+                | Instruction::SkipIfNotPressed { .. },
+            ) => {
+                pending.push(addr + 2);
+                pending.push(addr + 4);
+            }
+            // SCHIP's exit doesn't go anywhere
+            _ if opcode == 0x00FD => {}
+            Some(_) => pending.push(addr + 2),
+            None if extension_of(opcode).is_some() => pending.push(addr + size),
+            None => continue,
+        }
+        code.insert(addr, opcode);
+    }
+    code
+}
+
+/// Which extension an opcode belongs to, if any, and its form for reporting.
+fn extension_of(opcode: u16) -> Option<(Extension, &'static str)> {
+    let nibbles = (
+        (opcode >> 12) as u8,
+        (opcode >> 8 & 0xF) as u8,
+        (opcode >> 4 & 0xF) as u8,
+        (opcode & 0xF) as u8,
+    );
+    let found = match nibbles {
+        (0x0, 0x0, 0xC, _) => (Extension::Schip, "00CN (scroll down)"),
+        (0x0, 0x0, 0xD, _) => (Extension::XoChip, "00DN (scroll up)"),
+        (0x0, 0x0, 0xF, 0xB) => (Extension::Schip, "00FB (scroll right)"),
+        (0x0, 0x0, 0xF, 0xC) => (Extension::Schip, "00FC (scroll left)"),
+        (0x0, 0x0, 0xF, 0xD) => (Extension::Schip, "00FD (exit)"),
+        (0x0, 0x0, 0xF, 0xE) => (Extension::Schip, "00FE (low resolution)"),
+        (0x0, 0x0, 0xF, 0xF) => (Extension::Schip, "00FF (high resolution)"),
+        (0x5, _, _, 0x2) => (Extension::XoChip, "5XY2 (save range)"),
+        (0x5, _, _, 0x3) => (Extension::XoChip, "5XY3 (load range)"),
+        (0xD, _, _, 0x0) => (Extension::Schip, "DXY0 (16x16 sprite)"),
+        (0xF, 0x0, 0x0, 0x0) => (Extension::XoChip, "F000 (long index load)"),
+        (0xF, _, 0x0, 0x1) => (Extension::XoChip, "FN01 (plane select)"),
+        (0xF, 0x0, 0x0, 0x2) => (Extension::XoChip, "F002 (audio pattern)"),
+        (0xF, _, 0x3, 0x0) => (Extension::Schip, "FX30 (big font)"),
+        (0xF, _, 0x3, 0xA) => (Extension::XoChip, "FX3A (pitch)"),
+        (0xF, _, 0x7, 0x5) => (Extension::Schip, "FX75 (save flags)"),
+        (0xF, _, 0x8, 0x5) => (Extension::Schip, "FX85 (load flags)"),
+        _ => return None,
+    };
+    Some(found)
+}
+
+/// Quirks suggested by how the code uses shifts and loads and stores.
+fn quirk_hints(code: &BTreeMap<u16, u16>) -> Vec<(&'static str, bool, String)> {
+    let mut hints = Vec::new();
+
+    // A shift whose VY isn't VX only makes sense one way. Code written for in-place shifts
+    // tends to leave VY as V0, where the VIP's way needs a real source register
+    let shifts: Vec<(u8, u8)> = code
+        .values()
+        .filter_map(|&opcode| match Instruction::decode(opcode)? {
+            Instruction::ShiftRight { x, y } | Instruction::ShiftLeft { x, y } if x != y => {
+                Some((x, y))
+            }
+            _ => None,
+        })
+        .collect();
+    if !shifts.is_empty() {
+        let from_v0 = shifts.iter().filter(|&&(_, y)| y == 0).count();
+        if from_v0 * 2 > shifts.len() {
+            hints.push((
+                "shift",
+                true,
+                format!("{} of {} shifts name V0 as VY", from_v0, shifts.len()),
+            ));
+        } else {
+            hints.push((
+                "shift",
+                false,
+                format!(
+                    "VY isn't V0 in {} of {} shifts",
+                    shifts.len() - from_v0,
+                    shifts.len()
+                ),
+            ));
+        }
+    }
+
+    // A load or store straight after another without I being set in between relies on I having
+    // moved on past the first; moving I by hand after one means it doesn't move by itself
+    let mut relies_on_advance = BTreeSet::new();
+    let mut adjusts_after = BTreeSet::new();
+    let mut last_load_store = None;
+    for (&addr, &opcode) in code {
+        match Instruction::decode(opcode) {
+            Some(Instruction::StoreMemory { .. } | Instruction::LoadMemory { .. }) => {
+                if let Some(prev) = last_load_store.filter(|&prev| addr == prev + 2) {
+                    relies_on_advance.insert(prev);
+                }
+                last_load_store = Some(addr);
+                continue;
+            }
+            Some(Instruction::AddToIndex { .. })
+                if last_load_store.is_some_and(|prev| addr == prev + 2) =>
+            {
+                adjusts_after.insert(addr);
+            }
+            _ => {}
+        }
+        last_load_store = None;
+    }
+    if !relies_on_advance.is_empty() && adjusts_after.is_empty() {
+        hints.push((
+            "load-store",
+            false,
+            format!(
+                "loads or stores back to back without setting I {}",
+                times(relies_on_advance.len())
+            ),
+        ));
+    } else if !adjusts_after.is_empty() && relies_on_advance.is_empty() {
+        hints.push((
+            "load-store",
+            true,
+            format!(
+                "moves I by hand after a load or store {}",
+                times(adjusts_after.len())
+            ),
+        ));
+    }
+
+    hints
+}
+
+fn times(n: usize) -> String {
+    match n {
+        1 => "once".to_string(),
+        2 => "twice".to_string(),
+        n => format!("{} times", n),
+    }
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Confidence::Low => write!(f, "low"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::High => write!(f, "high"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_words(words: &[u16]) -> Guess {
+        let rom: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        detect(&rom, 0x200)
+    }
+
+    #[test]
+    fn schip_opcodes() {
+        // High resolution, a 16x16 sprite and the big font, then a loop
+        let guess = detect_words(&[0x00FF, 0xA20A, 0xD010, 0xF030, 0x1208]);
+        assert_eq!(guess.platform, Platform::Schip);
+        assert_eq!(guess.confidence, Confidence::High);
+        assert!(guess
+            .reasons
+            .contains(&"uses DXY0 (16x16 sprite) once".to_string()));
+    }
+
+    #[test]
+    fn xo_chip_opcodes() {
+        // SCHIP's high resolution too, but a long index load and plane select win
+        let guess = detect_words(&[0x00FF, 0xF000, 0x0300, 0xF201, 0x1208]);
+        assert_eq!(guess.platform, Platform::XoChip);
+        assert_eq!(guess.confidence, Confidence::High);
+    }
+
+    #[test]
+    fn unreachable_data_isnt_counted() {
+        // Jump over what would be 00FF, to a loop
+        let guess = detect_words(&[0x1204, 0x00FF, 0x1204]);
+        assert_eq!(guess.platform, Platform::Chip8);
+        assert_eq!(guess.confidence, Confidence::Low);
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::symbols::Symbols;
+
+// Consecutive data bytes are grouped onto lines of at most this many
+const DATA_BYTES_PER_LINE: usize = 8;
+// Mnemonics are padded to this width so the address comments line up
+const SOURCE_WIDTH: usize = 24;
+// Operands further than this past the nearest label are left as numbers
+const MAX_LABEL_OFFSET: u16 = 0x100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Decode every pair of bytes from the start of the ROM as an instruction.
+    Linear,
+    /// Follow jumps, calls and skips from the entry point, treating anything that is never
+    /// reached as data.
+    Recursive,
+}
+
+enum Item {
+    Code(Instruction),
+    Data,
+}
+
+struct Line {
+    addr: u16,
+    bytes: Vec<u8>,
+    item: Item,
+}
+
+/// A disassembled ROM. Its `Display` output is valid assembler source, with the address and raw
+/// bytes of every line in a trailing comment.
+pub struct Listing {
+    lines: Vec<Line>,
+    labels: BTreeMap<u16, String>,
+}
+
+/// Disassemble a ROM that is loaded at `base`, naming addresses after `symbols` where it can.
+pub fn disassemble(rom: &[u8], base: u16, mode: Mode, symbols: &Symbols) -> Listing {
+    let code = match mode {
+        Mode::Linear => linear_sweep(rom, base),
+        Mode::Recursive => recursive_descent(rom, base),
+    };
+
+    // Break data up at anything the code refers to or the symbols name, so it can be labelled
+    let targets: BTreeSet<u16> = code
+        .values()
+        .filter_map(target)
+        .chain(symbols.labels().map(|(addr, _)| addr))
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = base + offset as u16;
+        if let Some(&instruction) = code.get(&addr) {
+            lines.push(Line {
+                addr,
+                bytes: rom[offset..offset + 2].to_vec(),
+                item: Item::Code(instruction),
+            });
+            offset += 2;
+            continue;
+        }
+
+        // Run data on until the next instruction, or until the line is full
+        let mut end = offset + 1;
+        while end < rom.len()
+            && end - offset < DATA_BYTES_PER_LINE
+            && !code.contains_key(&(base + end as u16))
+            && !targets.contains(&(base + end as u16))
+        {
+            end += 1;
+        }
+        lines.push(Line {
+            addr,
+            bytes: rom[offset..end].to_vec(),
+            item: Item::Data,
+        });
+        offset = end;
+    }
+
+    let labels = make_labels(&lines, symbols);
+    Listing { lines, labels }
+}
+
+/// The address an instruction refers to, if it's one that gets a label.
+fn target(instruction: &Instruction) -> Option<u16> {
+    match *instruction {
+        Instruction::Jump { nnn }
+        | Instruction::JumpPlusV0 { nnn }
+        | Instruction::Call { nnn }
+        | Instruction::SetIndex { nnn } => Some(nnn),
+        _ => None,
+    }
+}
+
+/// Every aligned pair of bytes that decodes is an instruction.
+fn linear_sweep(rom: &[u8], base: u16) -> BTreeMap<u16, Instruction> {
+    rom.chunks_exact(2)
+        .enumerate()
+        .filter_map(|(n, pair)| {
+            let opcode = u16::from_be_bytes([pair[0], pair[1]]);
+            Instruction::decode(opcode).map(|i| (base + 2 * n as u16, i))
+        })
+        .collect()
+}
+
+/// Only instructions reachable from `base` are code.
+fn recursive_descent(rom: &[u8], base: u16) -> BTreeMap<u16, Instruction> {
+    let end = base as usize + rom.len();
+    let mut code = BTreeMap::new();
+    let mut pending = vec![base];
+
+    while let Some(addr) = pending.pop() {
+        if (addr as usize) < base as usize || addr as usize + 2 > end || code.contains_key(&addr) {
+            continue;
+        }
+        let offset = (addr - base) as usize;
+        let opcode = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+        let Some(instruction) = Instruction::decode(opcode) else {
+            continue;
+        };
+        code.insert(addr, instruction);
+
+        match instruction {
+            Instruction::Jump { nnn } => pending.push(nnn),
+            // The jump target depends on V0, so the best guess is the base address itself
+            Instruction::JumpPlusV0 { nnn } => pending.push(nnn),
+            Instruction::Return => {}
+            Instruction::Call { nnn } => {
+                pending.push(nnn);
+                pending.push(addr + 2);
+            }
+            Instruction::SkipIfEq { .. }
+            | Instruction::SkipIfNotEq { .. }
+            | Instruction::SkipIfEqReg { .. }
+            | Instruction::SkipIfNotEqReg { .. }
+            | Instruction::SkipIfPressed { .. }
+            | Instruction::SkipIfNotPressed { .. } => {
+                pending.push(addr + 2);
+                pending.push(addr + 4);
+            }
+            _ => pending.push(addr + 2),
+        }
+    }
+    code
+}
+
+/// Name every line that is the target of a call, jump or index load, or that has a name in the
+/// symbols. Targets that don't start a line (they're outside the ROM, or in the middle of an
+/// instruction) have to be referred to by offset from an earlier label.
+fn make_labels(lines: &[Line], symbols: &Symbols) -> BTreeMap<u16, String> {
+    let starts: BTreeSet<u16> = lines.iter().map(|l| l.addr).collect();
+
+    // When an address is reached several ways, calls win over jumps, which win over data
+    let mut kinds: BTreeMap<u16, (u8, &str)> = BTreeMap::new();
+    for line in lines {
+        let (rank, prefix, target) = match line.item {
+            Item::Code(Instruction::SetIndex { nnn }) => (0, "data", nnn),
+            Item::Code(Instruction::Jump { nnn } | Instruction::JumpPlusV0 { nnn }) => {
+                (1, "label", nnn)
+            }
+            Item::Code(Instruction::Call { nnn }) => (2, "sub", nnn),
+            _ => continue,
+        };
+        if starts.contains(&target) {
+            let kind = kinds.entry(target).or_insert((rank, prefix));
+            if rank > kind.0 {
+                *kind = (rank, prefix);
+            }
+        }
+    }
+
+    let mut labels: BTreeMap<u16, String> = kinds
+        .into_iter()
+        .map(|(target, (_, prefix))| (target, format!("{}_{:03X}", prefix, target)))
+        .collect();
+    for (addr, name) in symbols.labels() {
+        if starts.contains(&addr) {
+            labels.insert(addr, name.to_string());
+        }
+    }
+    labels
+}
+
+impl Listing {
+    /// Name an address after the label at or before it, as long as it's inside the ROM.
+    fn label(&self, addr: u16) -> Option<String> {
+        let last = self.lines.last()?;
+        if addr >= last.addr + last.bytes.len() as u16 {
+            return None;
+        }
+        let (&base, name) = self.labels.range(..=addr).next_back()?;
+        match addr - base {
+            0 => Some(name.clone()),
+            offset if offset < MAX_LABEL_OFFSET => Some(format!("{}+{:#X}", name, offset)),
+            _ => None,
+        }
+    }
+
+    fn source(&self, line: &Line) -> String {
+        let label = |addr: &u16| self.label(*addr);
+        match line.item {
+            Item::Data => {
+                let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+                format!("DB {}", bytes.join(", "))
+            }
+            Item::Code(instruction) => match instruction {
+                Instruction::Jump { nnn } => label(&nnn).map(|l| format!("JP {}", l)),
+                Instruction::Call { nnn } => label(&nnn).map(|l| format!("CALL {}", l)),
+                Instruction::SetIndex { nnn } => label(&nnn).map(|l| format!("LD I, {}", l)),
+                Instruction::JumpPlusV0 { nnn } => label(&nnn).map(|l| format!("JP V0, {}", l)),
+                _ => None,
+            }
+            .unwrap_or_else(|| instruction.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            writeln!(
+                f,
+                "    {:<width$} ; {:03X}: {}",
+                self.source(line),
+                line.addr,
+                bytes.join(" "),
+                width = SOURCE_WIDTH
+            )?;
+        }
+        Ok(())
+    }
+}
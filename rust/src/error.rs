@@ -0,0 +1,35 @@
+use std::fmt;
+
+use crate::ram::Address;
+
+/// Faults the emulated machine can run into. When one happens the instruction that caused it
+/// has been rolled back, so the PC still points at it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmulationError {
+    UnknownOpcode(u16),
+    MachineCall(u16),
+    StackUnderflow,
+    StackOverflow,
+    MemoryOutOfBounds(Address),
+    // The address written and the name of the read-only region it's in
+    ProtectedWrite(Address, &'static str),
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulationError::UnknownOpcode(opcode) => write!(f, "Unknown opcode {:#06X}", opcode),
+            EmulationError::MachineCall(nnn) => {
+                write!(f, "Machine code routine at {:#05X} is not supported", nnn)
+            }
+            EmulationError::StackUnderflow => write!(f, "Returned with an empty stack"),
+            EmulationError::StackOverflow => write!(f, "Called a subroutine with a full stack"),
+            EmulationError::MemoryOutOfBounds(addr) => {
+                write!(f, "Memory access out of bounds at {}", addr)
+            }
+            EmulationError::ProtectedWrite(addr, region) => {
+                write!(f, "Write to the read-only {} region at {}", region, addr)
+            }
+        }
+    }
+}
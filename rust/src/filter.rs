@@ -0,0 +1,321 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::palette::Palette;
+use crate::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+type Rgba = [u8; 4];
+
+/// An RGBA picture, row by row from the top left.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgba>,
+}
+
+impl Image {
+    /// The display in a palette's colors, from each pixel's brightness.
+    pub fn render(levels: impl Iterator<Item = u8>, palette: &Palette) -> Image {
+        Image {
+            width: DISPLAY_WIDTH,
+            height: DISPLAY_HEIGHT,
+            pixels: levels.map(|level| palette.pixel(level)).collect(),
+        }
+    }
+
+    /// The pixel at (x, y), with coordinates off the edge taken from the nearest one on it.
+    fn at(&self, x: isize, y: isize) -> Rgba {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    /// The picture made `n` times larger, each pixel becoming an n x n block.
+    pub fn scale(&self, n: usize) -> Image {
+        let width = self.width * n;
+        let pixels = (0..self.height * n)
+            .flat_map(|y| (0..width).map(move |x| (x / n, y / n)))
+            .map(|(x, y)| self.pixels[y * self.width + x])
+            .collect();
+        Image {
+            width,
+            height: self.height * n,
+            pixels,
+        }
+    }
+
+    /// The RGBA bytes, ready for a pixels frame or an image file.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.pixels.iter().flatten().copied().collect()
+    }
+}
+
+/// A pixel-art upscaler run over the picture before it's shown or saved. They all work on the
+/// CPU, so screenshots and recordings look just like the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    None,
+    /// Andrea Mazzoleni's Scale2x, which rounds off diagonal steps at double size.
+    Scale2x,
+    /// Scale2x's rules extended to triple size.
+    Scale3x,
+    /// Eric Johnston's EPX, which Scale2x grew from. It leaves corners where three or four of
+    /// the neighbors match alone, so it keeps a few more square corners.
+    Epx,
+    /// Double size, with every other row dimmed like a CRT's scanlines.
+    Scanlines,
+}
+
+impl Filter {
+    /// How many times larger than the display the filter's pictures are.
+    pub fn factor(self) -> usize {
+        match self {
+            Filter::None => 1,
+            Filter::Scale2x | Filter::Epx | Filter::Scanlines => 2,
+            Filter::Scale3x => 3,
+        }
+    }
+
+    pub fn apply(self, image: &Image) -> Image {
+        let factor = self.factor();
+        let mut out = Image {
+            width: image.width * factor,
+            height: image.height * factor,
+            pixels: vec![[0; 4]; image.pixels.len() * factor * factor],
+        };
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let block = match self {
+                    Filter::None => vec![image.at(x as isize, y as isize)],
+                    Filter::Scale2x => scale2x(image, x as isize, y as isize).to_vec(),
+                    Filter::Scale3x => scale3x(image, x as isize, y as isize).to_vec(),
+                    Filter::Epx => epx(image, x as isize, y as isize).to_vec(),
+                    Filter::Scanlines => {
+                        let p = image.at(x as isize, y as isize);
+                        let dim = [p[0] / 2, p[1] / 2, p[2] / 2, p[3]];
+                        vec![p, p, dim, dim]
+                    }
+                };
+                // Each source pixel becomes a factor x factor block, row by row
+                for (n, pixel) in block.into_iter().enumerate() {
+                    let (bx, by) = (n % factor, n / factor);
+                    out.pixels[(y * factor + by) * out.width + x * factor + bx] = pixel;
+                }
+            }
+        }
+        out
+    }
+}
+
+// The neighbors of E, named as in Scale2x's description:
+//   A B C
+//   D E F
+//   G H I
+struct Neighbors {
+    a: Rgba,
+    b: Rgba,
+    c: Rgba,
+    d: Rgba,
+    e: Rgba,
+    f: Rgba,
+    g: Rgba,
+    h: Rgba,
+    i: Rgba,
+}
+
+fn neighbors(image: &Image, x: isize, y: isize) -> Neighbors {
+    Neighbors {
+        a: image.at(x - 1, y - 1),
+        b: image.at(x, y - 1),
+        c: image.at(x + 1, y - 1),
+        d: image.at(x - 1, y),
+        e: image.at(x, y),
+        f: image.at(x + 1, y),
+        g: image.at(x - 1, y + 1),
+        h: image.at(x, y + 1),
+        i: image.at(x + 1, y + 1),
+    }
+}
+
+fn scale2x(image: &Image, x: isize, y: isize) -> [Rgba; 4] {
+    let Neighbors { b, d, e, f, h, .. } = neighbors(image, x, y);
+    if b != h && d != f {
+        [
+            if d == b { d } else { e },
+            if b == f { f } else { e },
+            if d == h { d } else { e },
+            if h == f { f } else { e },
+        ]
+    } else {
+        [e; 4]
+    }
+}
+
+fn scale3x(image: &Image, x: isize, y: isize) -> [Rgba; 9] {
+    let Neighbors {
+        a,
+        b,
+        c,
+        d,
+        e,
+        f,
+        g,
+        h,
+        i,
+    } = neighbors(image, x, y);
+    if b == h || d == f {
+        return [e; 9];
+    }
+    [
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        },
+        e,
+        if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        },
+        if h == f { f } else { e },
+    ]
+}
+
+fn epx(image: &Image, x: isize, y: isize) -> [Rgba; 4] {
+    let Neighbors {
+        b: above,
+        d: left,
+        e: p,
+        f: right,
+        h: below,
+        ..
+    } = neighbors(image, x, y);
+    let around = [above, right, left, below];
+    if around
+        .iter()
+        .any(|n| around.iter().filter(|&m| m == n).count() >= 3)
+    {
+        return [p; 4];
+    }
+    [
+        if left == above { above } else { p },
+        if above == right { right } else { p },
+        if below == left { left } else { p },
+        if right == below { below } else { p },
+    ]
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Filter, String> {
+        match s {
+            "none" => Ok(Filter::None),
+            "scale2x" => Ok(Filter::Scale2x),
+            "scale3x" => Ok(Filter::Scale3x),
+            "epx" => Ok(Filter::Epx),
+            "scanlines" => Ok(Filter::Scanlines),
+            _ => Err(format!(
+                "Unknown filter `{}` (expected none, scale2x, scale3x, epx or scanlines)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::None => write!(f, "none"),
+            Filter::Scale2x => write!(f, "scale2x"),
+            Filter::Scale3x => write!(f, "scale3x"),
+            Filter::Epx => write!(f, "epx"),
+            Filter::Scanlines => write!(f, "scanlines"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const O: Rgba = [0, 0, 0, 255];
+    const X: Rgba = [200, 100, 50, 255];
+
+    // A picture from rows of '#' (filled) and '.' (empty)
+    fn image(rows: &[&str]) -> Image {
+        Image {
+            width: rows[0].len(),
+            height: rows.len(),
+            pixels: rows
+                .iter()
+                .flat_map(|row| row.chars().map(|c| if c == '#' { X } else { O }))
+                .collect(),
+        }
+    }
+
+    // The block of the output that the middle pixel of a 3x3 picture became
+    fn middle(filter: Filter, rows: &[&str]) -> Vec<Rgba> {
+        let out = filter.apply(&image(rows));
+        let n = filter.factor();
+        (n..2 * n)
+            .flat_map(|y| (n..2 * n).map(move |x| (x, y)))
+            .map(|(x, y)| out.pixels[y * out.width + x])
+            .collect()
+    }
+
+    // A diagonal step, whose middle pixel is on its edge
+    const STEP: [&str; 3] = ["#..", "##.", "###"];
+
+    #[test]
+    fn scale2x_rounds_off_a_diagonal_step() {
+        assert_eq!(middle(Filter::Scale2x, &STEP), [X, O, X, X]);
+    }
+
+    #[test]
+    fn epx_rounds_off_a_diagonal_step() {
+        assert_eq!(middle(Filter::Epx, &STEP), [X, O, X, X]);
+    }
+
+    #[test]
+    fn epx_keeps_corners_where_three_neighbors_match() {
+        // The middle pixel's neighbors above, to the left and to the right all match
+        assert_eq!(middle(Filter::Epx, &["...", ".#.", "###"]), [X; 4]);
+    }
+
+    #[test]
+    fn scale3x_rounds_the_corner_of_a_step() {
+        // B above and D to the left of the empty middle pixel match each other, so its top left
+        // corner takes their color. The edges beside it stay, as E matches C and G
+        let block = middle(Filter::Scale3x, &["##.", "#..", "..."]);
+        assert_eq!(block, [X, O, O, O, O, O, O, O, O]);
+    }
+
+    #[test]
+    fn scanlines_dim_every_other_row() {
+        let out = Filter::Scanlines.apply(&image(&["#"]));
+        let dim = [100, 50, 25, 255];
+        assert_eq!(out.pixels, [X, X, dim, dim]);
+    }
+
+    #[test]
+    fn none_leaves_the_picture_alone() {
+        let picture = image(&STEP);
+        assert_eq!(Filter::None.apply(&picture), picture);
+    }
+}
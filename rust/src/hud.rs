@@ -0,0 +1,231 @@
+use std::time::{Duration, Instant};
+
+use crate::error::EmulationError;
+use crate::filter::Image;
+use crate::machine::Machine;
+
+// The rates are worked out over this long
+const RATE_PERIOD: Duration = Duration::from_secs(1);
+
+const TEXT: [u8; 4] = [255, 255, 255, 255];
+
+/// An overlay drawn over the picture, with how fast the emulator's running and the machine's
+/// registers, timers and keypad. It's drawn into the picture itself, so any frontend that shows
+/// the picture can show it.
+pub struct Hud {
+    shown: bool,
+    // The last instruction to fail, and where it was
+    error: Option<String>,
+    // What's been counted since the current period started
+    start: Instant,
+    draws: u64,
+    instructions: u64,
+    frames: u64,
+    // The rates over the last whole period
+    fps: u64,
+    ips: u64,
+    ticks_per_frame: Option<u64>,
+}
+
+impl Hud {
+    pub fn new(shown: bool) -> Hud {
+        Hud {
+            shown,
+            error: None,
+            start: Instant::now(),
+            draws: 0,
+            instructions: 0,
+            frames: 0,
+            fps: 0,
+            ips: 0,
+            ticks_per_frame: None,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.shown
+    }
+
+    pub fn toggle(&mut self) {
+        self.shown = !self.shown;
+    }
+
+    /// Count instructions run and emulated frames ended.
+    pub fn count(&mut self, instructions: usize, frames: u32) {
+        self.instructions += instructions as u64;
+        self.frames += u64::from(frames);
+    }
+
+    /// Count a picture drawn in the window.
+    pub fn count_draw(&mut self) {
+        self.draws += 1;
+    }
+
+    pub fn set_error(&mut self, e: &EmulationError, pc: u16) {
+        self.error = Some(format!("{} at {:03X}", e, pc));
+    }
+
+    /// Work out the rates once a period is up, returning whether they've changed.
+    pub fn update(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.start);
+        if elapsed < RATE_PERIOD {
+            return false;
+        }
+        let per_second = |count: u64| (count as f64 / elapsed.as_secs_f64()).round() as u64;
+        self.fps = per_second(self.draws);
+        self.ips = per_second(self.instructions);
+        self.ticks_per_frame = (self.frames > 0).then(|| self.instructions / self.frames);
+        self.start = now;
+        self.draws = 0;
+        self.instructions = 0;
+        self.frames = 0;
+        true
+    }
+
+    /// Draw the overlay in the top left of a picture. The text is larger on larger pictures,
+    /// and needs one at least 128x64, as at scale 2, to fit.
+    pub fn draw(&self, image: &mut Image, machine: &Machine) {
+        let size = (image.width / 320).max(1);
+        for (n, line) in self.lines(machine).iter().enumerate() {
+            draw_text(image, line, size, size, size + n * 6 * size);
+        }
+    }
+
+    fn lines(&self, machine: &Machine) -> Vec<String> {
+        let cpu = &machine.cpu;
+        let registers = |range: std::ops::Range<usize>| {
+            let values: Vec<String> = cpu.general_registers[range]
+                .iter()
+                .map(|v| format!("{:02X}", v))
+                .collect();
+            values.join(" ")
+        };
+        let keys: String = (0..16)
+            .map(|hex| {
+                if machine.keypad.is_held(hex) {
+                    char::from_digit(u32::from(hex), 16).unwrap_or('?')
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let ticks_per_frame = match self.ticks_per_frame {
+            Some(ticks) => ticks.to_string(),
+            None => "-".to_string(),
+        };
+
+        let mut lines = vec![
+            format!("FPS {}  IPS {}", self.fps, self.ips),
+            format!("TICKS/FRAME {}", ticks_per_frame),
+            format!(
+                "PC {:03X}  I {:03X}",
+                cpu.program_counter, cpu.index_register
+            ),
+            format!("V0-7 {}", registers(0..8)),
+            format!("V8-F {}", registers(8..16)),
+            format!("DT {:02X}  ST {:02X}", cpu.delay_timer, cpu.sound_timer),
+            format!("KEYS {}", keys),
+        ];
+        if let Some(error) = &self.error {
+            lines.push(error.clone());
+        }
+        lines
+    }
+}
+
+// Draw a line of text with its top left at (x, y), each of the font's pixels `size` pixels
+// across, on a darkened box so it can be read over anything
+fn draw_text(image: &mut Image, text: &str, size: usize, x: usize, y: usize) {
+    let width = text.chars().count() * 4 + 1;
+    for by in 0..7 * size {
+        for bx in 0..width * size {
+            if let Some(p) = pixel(
+                image,
+                (x + bx).wrapping_sub(size),
+                (y + by).wrapping_sub(size),
+            ) {
+                *p = [p[0] / 4, p[1] / 4, p[2] / 4, p[3]];
+            }
+        }
+    }
+    for (n, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (4 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..size {
+                    for dx in 0..size {
+                        let px = x + (n * 4 + column) * size + dx;
+                        if let Some(p) = pixel(image, px, y + row * size + dy) {
+                            *p = TEXT;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn pixel(image: &mut Image, x: usize, y: usize) -> Option<&mut [u8; 4]> {
+    if x < image.width && y < image.height {
+        Some(&mut image.pixels[y * image.width + x])
+    } else {
+        None
+    }
+}
+
+// A 3x5 font, a row at a time from the top, with the leftmost pixel in the highest bit.
+// Letters are all capitals, apart from the x in hex numbers
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        'x' => [0, 5, 2, 5, 0],
+        _ => match c.to_ascii_uppercase() {
+            '0' => [7, 5, 5, 5, 7],
+            '1' => [2, 6, 2, 2, 7],
+            '2' => [7, 1, 7, 4, 7],
+            '3' => [7, 1, 7, 1, 7],
+            '4' => [5, 5, 7, 1, 1],
+            '5' => [7, 4, 7, 1, 7],
+            '6' => [7, 4, 7, 5, 7],
+            '7' => [7, 1, 1, 2, 2],
+            '8' => [7, 5, 7, 5, 7],
+            '9' => [7, 5, 7, 1, 7],
+            'A' => [2, 5, 7, 5, 5],
+            'B' => [6, 5, 6, 5, 6],
+            'C' => [3, 4, 4, 4, 3],
+            'D' => [6, 5, 5, 5, 6],
+            'E' => [7, 4, 6, 4, 7],
+            'F' => [7, 4, 6, 4, 4],
+            'G' => [3, 4, 5, 5, 3],
+            'H' => [5, 5, 7, 5, 5],
+            'I' => [7, 2, 2, 2, 7],
+            'J' => [1, 1, 1, 5, 2],
+            'K' => [5, 5, 6, 5, 5],
+            'L' => [4, 4, 4, 4, 7],
+            'M' => [5, 7, 7, 5, 5],
+            'N' => [6, 5, 5, 5, 5],
+            'O' => [2, 5, 5, 5, 2],
+            'P' => [6, 5, 6, 4, 4],
+            'Q' => [2, 5, 5, 6, 3],
+            'R' => [6, 5, 6, 5, 5],
+            'S' => [3, 4, 2, 1, 6],
+            'T' => [7, 2, 2, 2, 2],
+            'U' => [5, 5, 5, 5, 7],
+            'V' => [5, 5, 5, 5, 2],
+            'W' => [5, 5, 7, 7, 5],
+            'X' => [5, 5, 2, 5, 5],
+            'Y' => [5, 5, 2, 2, 2],
+            'Z' => [7, 1, 2, 4, 7],
+            ' ' => [0, 0, 0, 0, 0],
+            ':' => [0, 2, 0, 2, 0],
+            '.' => [0, 0, 0, 0, 2],
+            ',' => [0, 0, 0, 2, 4],
+            '-' => [0, 0, 7, 0, 0],
+            '/' => [1, 1, 2, 4, 4],
+            '(' => [1, 2, 2, 2, 1],
+            ')' => [4, 2, 2, 2, 4],
+            _ => [7, 1, 3, 0, 2],
+        },
+    }
+}
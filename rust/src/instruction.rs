@@ -0,0 +1,186 @@
+use std::fmt;
+
+/// A decoded CHIP-8 instruction. This is the single opcode table for the emulator: the CPU
+/// dispatches on it, and everything that needs to name an instruction (traces, the disassembler)
+/// goes through it too, as does the assembler via `encode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    MachineCall { nnn: u16 },          // 0NNN
+    ClearScreen,                       // 00E0
+    Return,                            // 00EE
+    Jump { nnn: u16 },                 // 1NNN
+    Call { nnn: u16 },                 // 2NNN
+    SkipIfEq { x: u8, nn: u8 },        // 3XNN
+    SkipIfNotEq { x: u8, nn: u8 },     // 4XNN
+    SkipIfEqReg { x: u8, y: u8 },      // 5XY0
+    SetRegister { x: u8, nn: u8 },     // 6XNN
+    Add { x: u8, nn: u8 },             // 7XNN
+    SetVxToVy { x: u8, y: u8 },        // 8XY0
+    Or { x: u8, y: u8 },               // 8XY1
+    And { x: u8, y: u8 },              // 8XY2
+    Xor { x: u8, y: u8 },              // 8XY3
+    AddWithCarry { x: u8, y: u8 },     // 8XY4
+    SubtractVyFromVx { x: u8, y: u8 }, // 8XY5
+    ShiftRight { x: u8, y: u8 },       // 8XY6
+    SubtractVxFromVy { x: u8, y: u8 }, // 8XY7
+    ShiftLeft { x: u8, y: u8 },        // 8XYE
+    SkipIfNotEqReg { x: u8, y: u8 },   // 9XY0
+    SetIndex { nnn: u16 },             // ANNN
+    JumpPlusV0 { nnn: u16 },           // BNNN
+    Random { x: u8, nn: u8 },          // CXNN
+    Draw { x: u8, y: u8, n: u8 },      // DXYN
+    SkipIfPressed { x: u8 },           // EX9E
+    SkipIfNotPressed { x: u8 },        // EXA1
+    GetDelay { x: u8 },                // FX07
+    GetKey { x: u8 },                  // FX0A
+    SetDelay { x: u8 },                // FX15
+    SetSound { x: u8 },                // FX18
+    AddToIndex { x: u8 },              // FX1E
+    FontCharacter { x: u8 },           // FX29
+    BinaryCodedDecimal { x: u8 },      // FX33
+    StoreMemory { x: u8 },             // FX55
+    LoadMemory { x: u8 },              // FX65
+}
+
+impl Instruction {
+    /// Decode a raw opcode, or `None` if it isn't a CHIP-8 instruction.
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let nnn = opcode & 0x0FFF;
+        let nn = (opcode & 0x00FF) as u8;
+        let nibbles = (
+            ((opcode & 0xF000) >> 12) as u8,
+            ((opcode & 0x0F00) >> 8) as u8,
+            ((opcode & 0x00F0) >> 4) as u8,
+            (opcode & 0x000F) as u8,
+        );
+
+        let instruction = match nibbles {
+            (0x0, 0x0, 0xE, 0x0) => Instruction::ClearScreen,
+            (0x0, 0x0, 0xE, 0xE) => Instruction::Return,
+            (0x0, _, _, _) => Instruction::MachineCall { nnn },
+            (0x1, _, _, _) => Instruction::Jump { nnn },
+            (0x2, _, _, _) => Instruction::Call { nnn },
+            (0x3, x, _, _) => Instruction::SkipIfEq { x, nn },
+            (0x4, x, _, _) => Instruction::SkipIfNotEq { x, nn },
+            (0x5, x, y, 0x0) => Instruction::SkipIfEqReg { x, y },
+            (0x6, x, _, _) => Instruction::SetRegister { x, nn },
+            (0x7, x, _, _) => Instruction::Add { x, nn },
+            (0x8, x, y, 0x0) => Instruction::SetVxToVy { x, y },
+            (0x8, x, y, 0x1) => Instruction::Or { x, y },
+            (0x8, x, y, 0x2) => Instruction::And { x, y },
+            (0x8, x, y, 0x3) => Instruction::Xor { x, y },
+            (0x8, x, y, 0x4) => Instruction::AddWithCarry { x, y },
+            (0x8, x, y, 0x5) => Instruction::SubtractVyFromVx { x, y },
+            (0x8, x, y, 0x6) => Instruction::ShiftRight { x, y },
+            (0x8, x, y, 0x7) => Instruction::SubtractVxFromVy { x, y },
+            (0x8, x, y, 0xE) => Instruction::ShiftLeft { x, y },
+            (0x9, x, y, 0x0) => Instruction::SkipIfNotEqReg { x, y },
+            (0xA, _, _, _) => Instruction::SetIndex { nnn },
+            (0xB, _, _, _) => Instruction::JumpPlusV0 { nnn },
+            (0xC, x, _, _) => Instruction::Random { x, nn },
+            (0xD, x, y, n) => Instruction::Draw { x, y, n },
+            (0xE, x, 0x9, 0xE) => Instruction::SkipIfPressed { x },
+            (0xE, x, 0xA, 0x1) => Instruction::SkipIfNotPressed { x },
+            (0xF, x, 0x0, 0x7) => Instruction::GetDelay { x },
+            (0xF, x, 0x0, 0xA) => Instruction::GetKey { x },
+            (0xF, x, 0x1, 0x5) => Instruction::SetDelay { x },
+            (0xF, x, 0x1, 0x8) => Instruction::SetSound { x },
+            (0xF, x, 0x1, 0xE) => Instruction::AddToIndex { x },
+            (0xF, x, 0x2, 0x9) => Instruction::FontCharacter { x },
+            (0xF, x, 0x3, 0x3) => Instruction::BinaryCodedDecimal { x },
+            (0xF, x, 0x5, 0x5) => Instruction::StoreMemory { x },
+            (0xF, x, 0x6, 0x5) => Instruction::LoadMemory { x },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+
+    /// The raw opcode for this instruction; the inverse of `decode`.
+    pub fn encode(&self) -> u16 {
+        let op = |high: u16, x: u8, y: u8, low: u16| {
+            (high << 12) | (u16::from(x) << 8) | (u16::from(y) << 4) | low
+        };
+        let op_nn = |high: u16, x: u8, nn: u8| (high << 12) | (u16::from(x) << 8) | u16::from(nn);
+
+        match *self {
+            Instruction::MachineCall { nnn } => nnn,
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::Jump { nnn } => 0x1000 | nnn,
+            Instruction::Call { nnn } => 0x2000 | nnn,
+            Instruction::SkipIfEq { x, nn } => op_nn(0x3, x, nn),
+            Instruction::SkipIfNotEq { x, nn } => op_nn(0x4, x, nn),
+            Instruction::SkipIfEqReg { x, y } => op(0x5, x, y, 0x0),
+            Instruction::SetRegister { x, nn } => op_nn(0x6, x, nn),
+            Instruction::Add { x, nn } => op_nn(0x7, x, nn),
+            Instruction::SetVxToVy { x, y } => op(0x8, x, y, 0x0),
+            Instruction::Or { x, y } => op(0x8, x, y, 0x1),
+            Instruction::And { x, y } => op(0x8, x, y, 0x2),
+            Instruction::Xor { x, y } => op(0x8, x, y, 0x3),
+            Instruction::AddWithCarry { x, y } => op(0x8, x, y, 0x4),
+            Instruction::SubtractVyFromVx { x, y } => op(0x8, x, y, 0x5),
+            Instruction::ShiftRight { x, y } => op(0x8, x, y, 0x6),
+            Instruction::SubtractVxFromVy { x, y } => op(0x8, x, y, 0x7),
+            Instruction::ShiftLeft { x, y } => op(0x8, x, y, 0xE),
+            Instruction::SkipIfNotEqReg { x, y } => op(0x9, x, y, 0x0),
+            Instruction::SetIndex { nnn } => 0xA000 | nnn,
+            Instruction::JumpPlusV0 { nnn } => 0xB000 | nnn,
+            Instruction::Random { x, nn } => op_nn(0xC, x, nn),
+            Instruction::Draw { x, y, n } => op(0xD, x, y, u16::from(n)),
+            Instruction::SkipIfPressed { x } => op_nn(0xE, x, 0x9E),
+            Instruction::SkipIfNotPressed { x } => op_nn(0xE, x, 0xA1),
+            Instruction::GetDelay { x } => op_nn(0xF, x, 0x07),
+            Instruction::GetKey { x } => op_nn(0xF, x, 0x0A),
+            Instruction::SetDelay { x } => op_nn(0xF, x, 0x15),
+            Instruction::SetSound { x } => op_nn(0xF, x, 0x18),
+            Instruction::AddToIndex { x } => op_nn(0xF, x, 0x1E),
+            Instruction::FontCharacter { x } => op_nn(0xF, x, 0x29),
+            Instruction::BinaryCodedDecimal { x } => op_nn(0xF, x, 0x33),
+            Instruction::StoreMemory { x } => op_nn(0xF, x, 0x55),
+            Instruction::LoadMemory { x } => op_nn(0xF, x, 0x65),
+        }
+    }
+}
+
+/// Mnemonics follow Cowgod's technical reference, with every number written in hex.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::MachineCall { nnn } => write!(f, "SYS {:#05X}", nnn),
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::Jump { nnn } => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call { nnn } => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SkipIfEq { x, nn } => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipIfNotEq { x, nn } => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipIfEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SetRegister { x, nn } => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::Add { x, nn } => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::SetVxToVy { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddWithCarry { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubtractVyFromVx { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubtractVxFromVy { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipIfNotEqReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetIndex { nnn } => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JumpPlusV0 { nnn } => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Random { x, nn } => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {:#03X}", x, y, n),
+            Instruction::SkipIfPressed { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::GetDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::GetKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddToIndex { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::FontCharacter { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::BinaryCodedDecimal { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreMemory { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadMemory { x } => write!(f, "LD V{:X}, [I]", x),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use crate::keypad::Keypad;
+
+/// Which keyboard keys stand for each of the sixteen hex keys. A hex key can have several.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<VirtualKeyCode>; 16],
+}
+
+// The VIP's keypad, laid out on the left of a QWERTY keyboard:
+//   1 2 3 C     1 2 3 4
+//   4 5 6 D     Q W E R
+//   7 8 9 E     A S D F
+//   A 0 B F     Z X C V
+const STANDARD: [VirtualKeyCode; 16] = {
+    use VirtualKeyCode::*;
+    [X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V]
+};
+
+// The digits then A to F in rows of four, the way this emulator used to map them
+const LEGACY: [VirtualKeyCode; 16] = {
+    use VirtualKeyCode::*;
+    [
+        Key1, Key2, Key3, C, Key4, Key5, Key6, D, Key7, Key8, Key9, E, A, Key0, B, F,
+    ]
+};
+
+/// The profiles that are always there, which config files can add to.
+pub const BUILT_IN_PROFILES: &[&str] = &["standard", "legacy"];
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::from_keys(STANDARD)
+    }
+}
+
+impl Keymap {
+    fn from_keys(keys: [VirtualKeyCode; 16]) -> Keymap {
+        Keymap {
+            keys: keys.map(|key| vec![key]),
+        }
+    }
+
+    /// One of the built-in profiles, by name.
+    pub fn built_in(name: &str) -> Option<Keymap> {
+        match name {
+            "standard" => Some(Keymap::from_keys(STANDARD)),
+            "legacy" => Some(Keymap::from_keys(LEGACY)),
+            _ => None,
+        }
+    }
+
+    /// The keyboard keys bound to a hex key.
+    pub fn keys(&self, hex: u8) -> &[VirtualKeyCode] {
+        &self.keys[hex as usize]
+    }
+
+    /// Bind keyboard keys to a hex key in place of the ones it had. Each keyboard key is taken
+    /// from any other hex key it was bound to, so one press never means two hex keys.
+    pub fn bind(&mut self, hex: u8, keys: &[VirtualKeyCode]) {
+        for bound in &mut self.keys {
+            bound.retain(|key| !keys.contains(key));
+        }
+        self.keys[hex as usize] = keys.to_vec();
+    }
+
+    /// Whether a keyboard key is bound to any hex key, which takes it away from the window's
+    /// own shortcuts.
+    pub fn is_bound(&self, key: VirtualKeyCode) -> bool {
+        self.keys.iter().any(|keys| keys.contains(&key))
+    }
+
+    /// Update the keypad from the keys held on the keyboard.
+    pub fn update(&self, input: &WinitInputHelper, keypad: &mut Keypad) {
+        for (hex, keys) in self.keys.iter().enumerate() {
+            keypad.set_held(hex as u8, keys.iter().any(|&key| input.key_held(key)));
+        }
+    }
+}
+
+// The names keys go by in config files, which are winit's without the `Key` on the digits
+const KEY_NAMES: &[(&str, VirtualKeyCode)] = {
+    use VirtualKeyCode::*;
+    &[
+        ("0", Key0),
+        ("1", Key1),
+        ("2", Key2),
+        ("3", Key3),
+        ("4", Key4),
+        ("5", Key5),
+        ("6", Key6),
+        ("7", Key7),
+        ("8", Key8),
+        ("9", Key9),
+        ("A", A),
+        ("B", B),
+        ("C", C),
+        ("D", D),
+        ("E", E),
+        ("F", F),
+        ("G", G),
+        ("H", H),
+        ("I", I),
+        ("J", J),
+        ("K", K),
+        ("L", L),
+        ("M", M),
+        ("N", N),
+        ("O", O),
+        ("P", P),
+        ("Q", Q),
+        ("R", R),
+        ("S", S),
+        ("T", T),
+        ("U", U),
+        ("V", V),
+        ("W", W),
+        ("X", X),
+        ("Y", Y),
+        ("Z", Z),
+        ("Numpad0", Numpad0),
+        ("Numpad1", Numpad1),
+        ("Numpad2", Numpad2),
+        ("Numpad3", Numpad3),
+        ("Numpad4", Numpad4),
+        ("Numpad5", Numpad5),
+        ("Numpad6", Numpad6),
+        ("Numpad7", Numpad7),
+        ("Numpad8", Numpad8),
+        ("Numpad9", Numpad9),
+        ("Up", Up),
+        ("Down", Down),
+        ("Left", Left),
+        ("Right", Right),
+        ("Space", Space),
+        ("Enter", Return),
+        ("Tab", Tab),
+        ("Backspace", Back),
+        ("Comma", Comma),
+        ("Period", Period),
+        ("Slash", Slash),
+        ("Semicolon", Semicolon),
+    ]
+};
+
+/// Parse a key name like `Q`, `7` or `Numpad4`, ignoring case.
+pub fn parse_key(name: &str) -> Result<VirtualKeyCode, String> {
+    KEY_NAMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+        .ok_or(format!("Unknown key `{}`", name))
+}
+
+pub fn key_name(key: VirtualKeyCode) -> &'static str {
+    KEY_NAMES
+        .iter()
+        .find(|&&(_, k)| k == key)
+        .map(|&(name, _)| name)
+        .expect("keymaps only hold keys that have names")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::config;
+    use VirtualKeyCode::*;
+
+    #[test]
+    fn binding_takes_keys_from_other_hex_keys() {
+        let mut keymap = Keymap::default();
+        assert_eq!(keymap.keys(0x5), [W]);
+        assert_eq!(keymap.keys(0x6), [E]);
+
+        keymap.bind(0x5, &[Up, E]);
+        assert_eq!(keymap.keys(0x5), [Up, E]);
+        // E was 6's, so 6 is left with nothing
+        assert!(keymap.keys(0x6).is_empty());
+        assert!(keymap.is_bound(Up));
+        assert!(!keymap.is_bound(W));
+    }
+
+    #[test]
+    fn switching_profiles() {
+        let mut defined = BTreeMap::new();
+        defined.insert(
+            "arrows".to_string(),
+            vec![(0x2, vec![Up]), (0x8, vec![Down])],
+        );
+
+        assert_eq!(
+            config::profile("standard", &defined).unwrap(),
+            Keymap::default()
+        );
+        let legacy = config::profile("legacy", &defined).unwrap();
+        assert_eq!(legacy.keys(0x0), [Key1]);
+        assert_eq!(legacy.keys(0xF), [F]);
+
+        // A defined profile is the standard layout with its bindings on top
+        let arrows = config::profile("arrows", &defined).unwrap();
+        assert_eq!(arrows.keys(0x2), [Up]);
+        assert_eq!(arrows.keys(0x8), [Down]);
+        assert_eq!(arrows.keys(0x5), [W]);
+
+        assert_eq!(
+            config::profile("dvorak", &defined).unwrap_err(),
+            "Unknown keymap profile `dvorak` (expected standard, legacy, arrows)"
+        );
+    }
+}
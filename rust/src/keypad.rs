@@ -0,0 +1,33 @@
+/// The sixteen hex keys, as the frontend last saw them. The CPU only knows hex keys; which
+/// keyboard keys stand for them is up to the frontend.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Keypad {
+    held: [bool; 16],
+    // The last key to go down that FX0A hasn't taken yet
+    pressed: Option<u8>,
+}
+
+impl Keypad {
+    /// Whether a hex key is held. Only the low digit counts, as on the VIP.
+    pub fn is_held(&self, hex: u8) -> bool {
+        self.held[(hex & 0xF) as usize]
+    }
+
+    pub fn set_held(&mut self, hex: u8, held: bool) {
+        let key = &mut self.held[hex as usize];
+        if held && !*key {
+            self.pressed = Some(hex);
+        }
+        *key = held;
+    }
+
+    /// The key pressed since the last call, if there was one.
+    pub fn take_pressed(&mut self) -> Option<u8> {
+        self.pressed.take()
+    }
+
+    /// Put back a press that `take_pressed` took, when the instruction that took it is undone.
+    pub fn restore_pressed(&mut self, hex: u8) {
+        self.pressed = Some(hex);
+    }
+}
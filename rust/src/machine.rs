@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use log::{error, warn};
+
+use crate::backtrace;
+use crate::cpu::CPU;
+use crate::delta::{Change, Delta};
+use crate::error::EmulationError;
+use crate::keypad::Keypad;
+use crate::ram::{MemoryConfig, RAM};
+use crate::rom::RomInfo;
+use crate::stack::{Stack, StackConfig};
+use crate::symbols::Symbols;
+use crate::trace::{TraceEntry, Tracer};
+use crate::{Vram, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+// How many of the most recently executed instructions to keep for crash reports
+const RECENT_LIMIT: usize = 64;
+
+/// All of the device components, bundled so they can be stepped (and un-stepped) together.
+pub struct Machine {
+    pub cpu: CPU,
+    pub stack: Stack,
+    pub vram: Vram, // access at vram[y][x]
+    pub ram: RAM,
+    // Kept up to date by the frontend
+    pub keypad: Keypad,
+    pub tracer: Option<Tracer>,
+    pub symbols: Symbols,
+    recent: VecDeque<TraceEntry>,
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        Machine {
+            cpu: CPU::new(),
+            stack: Stack::new(StackConfig::default()),
+            vram: [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            ram: RAM::new(MemoryConfig::default()),
+            keypad: Keypad::default(),
+            tracer: None,
+            symbols: Symbols::default(),
+            recent: VecDeque::with_capacity(RECENT_LIMIT),
+        }
+    }
+
+    /// Start afresh with `rom` loaded at `addr`, as if nothing had run: the CPU, stack, VRAM
+    /// and RAM are all made anew, keeping their configuration. A ROM that can't be loaded
+    /// leaves the machine untouched.
+    pub fn reset(&mut self, rom: &[u8], addr: u16) -> Result<RomInfo, String> {
+        let mut ram = RAM::new(self.ram.config());
+        let info = ram.load_rom_bytes(rom, addr)?;
+        let quirks = self.cpu.quirks;
+        self.cpu = CPU::new();
+        self.cpu.quirks = quirks;
+        self.cpu.program_counter = addr;
+        self.stack = Stack::new(self.stack.config());
+        self.vram = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        self.ram = ram;
+        self.recent.clear();
+        Ok(info)
+    }
+
+    /// Execute one instruction and return the delta needed to undo it. A faulting instruction
+    /// leaves the machine untouched.
+    pub fn step(&mut self) -> Result<Delta, EmulationError> {
+        let entry = TraceEntry::capture(&self.cpu, &self.stack, &self.ram);
+        if let Some(tracer) = &mut self.tracer {
+            if let Err(e) = tracer.trace(&entry, &self.symbols) {
+                error!("Failed to write trace, tracing disabled: {}", e);
+                self.tracer = None;
+            }
+        }
+
+        let delta = self.cpu.emulate_cycle(
+            &mut self.keypad,
+            &mut self.stack,
+            &mut self.vram,
+            &mut self.ram,
+        )?;
+
+        if self.recent.len() == RECENT_LIMIT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(entry);
+
+        if let Some(callee) = self.runaway_recursion(&delta) {
+            warn!(
+                "Runaway recursion into {}, {} of {} stack entries used",
+                self.symbols.describe(callee),
+                self.stack.depth(),
+                self.stack.capacity()
+            );
+        }
+        Ok(delta)
+    }
+
+    /// The subroutine recursing out of control, if the CALL that `delta` records has just
+    /// taken the stack close to full with calls into it.
+    pub fn runaway_recursion(&self, delta: &Delta) -> Option<u16> {
+        if !delta
+            .changes()
+            .iter()
+            .any(|c| matches!(c, Change::StackEntry { .. }))
+        {
+            return None;
+        }
+        backtrace::runaway_recursion(&self.stack, &self.ram)
+    }
+
+    /// A formatted backtrace of the current call stack, innermost frame first.
+    pub fn backtrace(&self) -> Vec<String> {
+        let frames = backtrace::frames(&self.stack, &self.ram);
+        backtrace::format(self.cpu.program_counter, &frames, &self.symbols)
+    }
+
+    /// The most recently executed instructions, oldest first.
+    pub fn recent(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.recent.iter()
+    }
+
+    /// Roll back an instruction previously returned by `step`. Deltas must be undone in the
+    /// reverse of the order they were produced.
+    pub fn undo(&mut self, delta: &Delta) {
+        self.recent.pop_back();
+        delta.revert(
+            &mut self.cpu,
+            &mut self.keypad,
+            &mut self.stack,
+            &mut self.vram,
+            &mut self.ram,
+        );
+    }
+}
@@ -1,5 +1,9 @@
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
 
 use log::error;
 use pixels::{Error, Pixels, SurfaceTexture};
@@ -9,39 +13,290 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
+mod archive;
+mod asm;
+mod backtrace;
+mod cartridge;
+mod cli;
+mod clock;
+mod config;
 mod cpu;
+mod crash;
+mod debugger;
+mod delta;
+mod detect;
+mod disasm;
+mod error;
+mod filter;
+mod hud;
+mod instruction;
+mod keymap;
+mod keypad;
+mod machine;
+mod octo;
+mod palette;
+mod phosphor;
+mod present;
+mod quirks;
 mod ram;
+mod recording;
+mod reload;
+mod rom;
+mod romdb;
+mod screen;
+mod screenshot;
+mod snapshot;
 mod stack;
+mod symbols;
+mod trace;
 
-use crate::cpu::CPU;
-use crate::ram::RAM;
+use crate::cli::Command;
+use crate::clock::{Clock, FRAME_TIME};
+use crate::config::{Frontend, Layer, Settings};
+use crate::debugger::Debugger;
+use crate::detect::Confidence;
+use crate::error::EmulationError;
+use crate::hud::Hud;
+use crate::machine::Machine;
+use crate::ram::{Address, MEM_BYTES, PROG_MEM_START, RAM};
+use crate::recording::Recorder;
+use crate::reload::Reloader;
+use crate::rom::RomInfo;
+use crate::screen::Screen;
 use crate::stack::Stack;
+use crate::symbols::Symbols;
+use crate::trace::Tracer;
 
 pub const DISPLAY_HEIGHT: usize = 32;
 pub const DISPLAY_WIDTH: usize = 64;
 
+// The speed a recording made without a window, or the debugger, runs at when none is set, which
+// most CHIP-8 games play well at
+const DEFAULT_TICKS_PER_FRAME: usize = 10;
+
+pub type Vram = [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+
 fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().collect();
+    let (options, debug) = match cli::parse(&args) {
+        Ok(Command::Run(options)) => (options, false),
+        Ok(Command::Debug(options)) => (options, true),
+        Ok(Command::Disasm {
+            rom,
+            mode,
+            symbols,
+            load_address,
+        }) => exit_with(disassemble(&rom, mode, symbols.as_deref(), load_address)),
+        Ok(Command::Asm { source, output }) => exit_with(assemble(&source, &output)),
+        Ok(Command::List { paths }) => exit_with(list_roms(&paths)),
+        Ok(Command::Info { rom, entry }) => exit_with(show_info(&rom, entry.as_deref())),
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
     // Instantiate device components
-    let mut ram = RAM::new();
-    let mut stack = Stack::new();
-    let mut vram = [[u8::from(0); DISPLAY_WIDTH]; DISPLAY_HEIGHT]; // access at vram[y][x]
-    let mut cpu = CPU::new();
+    let mut machine = Machine::new();
+    machine.stack = Stack::new(options.stack);
+    machine.ram = RAM::new(options.memory);
+    if let Some(config) = &options.trace {
+        match Tracer::create(config) {
+            Ok(tracer) => machine.tracer = Some(tracer),
+            Err(e) => error!("Could not create trace file {}: {}", config.path, e),
+        }
+    }
+
+    match load_symbols(options.symbols.as_deref(), options.rom.as_deref()) {
+        Ok(symbols) => machine.symbols = symbols,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    // The settings are laid over each other in order: the defaults, the global config file, what
+    // a cartridge or the ROM database gives, the ROM's config file, then the command line
+    let mut settings = Settings::default();
+    let global = match &options.config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => config::global_path().filter(|path| path.exists()),
+    };
+    if let Some(path) = global {
+        apply_config(&path, &mut settings);
+    }
 
     // Load file into memory
-    let args: Vec<String> = env::args().collect();
-    let filename = args.get(1).expect("No ROM filename provided.");
-    ram.load_rom(File::open(filename).unwrap());
-    println!("Loaded ROM.");
+    let mut reloader = None;
+    if let Some(rom) = &options.rom {
+        let addr = options.load_address;
+        let cartridge = cartridge::is_cartridge(Path::new(rom));
+        let mut entry = options.entry.clone();
+        let result = if rom == "-" {
+            machine.ram.load_rom_reader(io::stdin().lock(), addr)
+        } else if cartridge {
+            cartridge::load(Path::new(rom)).and_then(|cart| {
+                settings.quirks = cart.quirks;
+                settings.palette = cart.palette;
+                if cart.tickrate.is_some() {
+                    settings.ticks_per_frame = cart.tickrate;
+                }
+                if machine.symbols.is_empty() {
+                    machine.symbols = cart.program.symbols;
+                }
+                machine.ram.load_rom_bytes(&cart.program.rom, addr)
+            })
+        } else {
+            let path = Path::new(rom);
+            entry = entry.or_else(|| choose_entry(path));
+            machine.ram.load_rom_file(path, entry.as_deref(), addr)
+        };
+        let info = match result {
+            Ok(info) => info,
+            Err(e) => {
+                eprintln!("Could not load ROM {}: {}", rom, e);
+                process::exit(1);
+            }
+        };
+        eprintln!("Loaded ROM ({}).", info);
+        machine.cpu.program_counter = addr;
+        let path = Some(PathBuf::from(rom)).filter(|_| rom != "-");
+        let bytes = machine.ram.rom(&info).to_vec();
+        reloader = Some(Reloader::new(path, entry, addr, bytes));
+
+        if options.rom_db && !cartridge {
+            match romdb::lookup(&info.sha1) {
+                Ok(Some(entry)) => {
+                    eprintln!("{}", entry);
+                    if !entry.keys.is_empty() {
+                        let keys: Vec<String> = entry
+                            .keys
+                            .iter()
+                            .map(|(key, hint)| format!("{:X} {}", key, hint))
+                            .collect();
+                        eprintln!("Keys: {}", keys.join(", "));
+                    }
+                    settings.quirks = entry.quirks;
+                    if entry.tickrate.is_some() {
+                        settings.ticks_per_frame = entry.tickrate;
+                    }
+                    settings.palette = entry.palette.unwrap_or(settings.palette);
+                }
+                // Not a ROM anyone has catalogued, so go by what its code looks like
+                Ok(None) => {
+                    let guess = detect::detect(machine.ram.rom(&info), addr);
+                    if guess.confidence > Confidence::Low {
+                        eprintln!(
+                            "Looks like a {} ROM ({} confidence)",
+                            guess.platform, guess.confidence
+                        );
+                        settings.quirks = guess.profile();
+                    }
+                }
+                Err(e) => error!("{}", e),
+            }
+        }
+
+        let path = config::rom_path(Path::new(rom));
+        if rom != "-" && path.exists() {
+            apply_config(&path, &mut settings);
+        }
+    }
+    if let Err(e) = options.overrides.apply(&mut settings) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    if debug {
+        settings.frontend = Frontend::Debugger;
+    }
+    // What was loaded is reported on standard error, so this reads back in as a config file
+    if options.print_config {
+        let _ = write!(io::stdout().lock(), "{}", settings);
+        process::exit(0);
+    }
+    machine.cpu.quirks = settings.quirks;
+
+    if let Some(path) = &options.snapshot {
+        let result =
+            File::open(path).and_then(|f| snapshot::load(&mut machine, &mut BufReader::new(f)));
+        if let Err(e) = result {
+            eprintln!("Could not load snapshot {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+
+    if settings.frontend == Frontend::Debugger {
+        let ticks_per_frame = settings.ticks_per_frame.unwrap_or(DEFAULT_TICKS_PER_FRAME);
+        Debugger::new(machine, ticks_per_frame).run();
+        return Ok(());
+    }
+
+    let screen = Screen::new(&settings);
+    let (width, height) = screen.filtered_size();
+    let scale = screen.scale();
+    let record = |path: &str, format| {
+        let size = ((width * scale) as usize, (height * scale) as usize);
+        Recorder::create(Path::new(path), format, size.0, size.1)
+            .map_err(|e| format!("Could not start recording to {}: {}", path, e))
+    };
+    let recorder = match &options.record {
+        Some((path, format)) => match record(path, *format) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // With a number of frames to record, there's no need for a window
+    if let Some(frames) = options.frames {
+        let recorder = recorder.expect("--frames is only accepted along with a recording");
+        let ticks = settings.ticks_per_frame.unwrap_or(DEFAULT_TICKS_PER_FRAME);
+        exit_with(record_headless(machine, screen, recorder, ticks, frames));
+    }
+
+    if let Some(reloader) = &mut reloader {
+        if options.watch {
+            reloader.watch(options.keep_state);
+        }
+    }
+    run_window(machine, screen, recorder, reloader, settings)
+}
+
+/// Run the ROM in a window, until it's closed.
+fn run_window(
+    mut machine: Machine,
+    mut screen: Screen,
+    mut recorder: Option<Recorder>,
+    mut reloader: Option<Reloader>,
+    settings: Settings,
+) -> Result<(), Error> {
+    let (width, height) = screen.filtered_size();
+    let scale = screen.scale();
+    // The overlay needs room for its text, so while it's shown the picture is drawn at the
+    // window's size rather than scaled up to it
+    let mut hud = Hud::new(settings.hud);
+    let buffer_size = move |hud: &Hud| {
+        if hud.is_shown() {
+            (width * scale, height * scale)
+        } else {
+            (width, height)
+        }
+    };
 
     // Setup Pixels context
-    env_logger::init();
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
     let window = {
         let size = LogicalSize::new(DISPLAY_WIDTH as f64, DISPLAY_HEIGHT as f64);
+        let scaled = LogicalSize::new((width * scale) as f64, (height * scale) as f64);
         WindowBuilder::new()
             .with_title("Chip-8")
-            .with_inner_size(size)
+            .with_inner_size(scaled)
             .with_min_inner_size(size)
             .build(&event_loop)
             .unwrap()
@@ -50,22 +305,28 @@ fn main() -> Result<(), Error> {
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32, surface_texture)?
+        let (width, height) = buffer_size(&hud);
+        Pixels::new(width, height, surface_texture)?
     };
 
     // Main event loop
+    let paced = settings.ticks_per_frame.is_some();
+    let mut clock = Clock::new(paced, settings.fast_forward);
+    // A ROM that crashes is stopped until it's started again
+    let mut crashed = false;
+    let mut title = String::from("Chip-8");
     event_loop.run(move |event, _, control_flow| {
         // Draw the current frame from the contents of the VRAM
         if let Event::RedrawRequested(_) = event {
-            let frame = pixels.get_frame();
-            // Update frame with contents of device's VRAM
-            for (chunk, vram_pix) in frame.chunks_exact_mut(4).zip(vram.iter().flatten()) {
-                let new_chunk = match *vram_pix {
-                    0 => [0, 0, 0, 255],
-                    _ => [255, 255, 255, 255],
-                };
-                chunk.copy_from_slice(&new_chunk);
-            }
+            let image = if hud.is_shown() {
+                let mut image = screen.image();
+                hud.draw(&mut image, &machine);
+                image
+            } else {
+                screen.filtered()
+            };
+            pixels.get_frame().copy_from_slice(&image.bytes());
+            hud.count_draw();
             if pixels
                 .render()
                 .map_err(|e| error!("pixels.render() failed: {}", e))
@@ -80,18 +341,407 @@ fn main() -> Result<(), Error> {
         if input.update(&event) {
             // Close events
             if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+                stop_recording(recorder.take());
                 *control_flow = ControlFlow::Exit;
                 return;
             }
 
+            // Save a screenshot of the display as it's shown
+            if input.key_pressed(VirtualKeyCode::F12) {
+                match screenshot::save(&screen.image()) {
+                    Ok(path) => println!("Screenshot saved to {}", path.display()),
+                    Err(e) => error!("Could not save screenshot: {}", e),
+                }
+            }
+
+            // Start or stop recording
+            if input.key_pressed(VirtualKeyCode::F9) {
+                if recorder.is_some() {
+                    stop_recording(recorder.take());
+                } else {
+                    let size = ((width * scale) as usize, (height * scale) as usize);
+                    match Recorder::start(settings.record_format, size.0, size.1) {
+                        Ok(started) => {
+                            println!("Recording to {}", started.path().display());
+                            recorder = Some(started);
+                        }
+                        Err(e) => error!("Could not start recording: {}", e),
+                    }
+                }
+            }
+
+            // Show or hide the overlay
+            if input.key_pressed(VirtualKeyCode::F3) {
+                hud.toggle();
+                let (width, height) = buffer_size(&hud);
+                pixels.resize_buffer(width, height);
+                window.request_redraw();
+            }
+
             // Resize the window
             if let Some(size) = input.window_resized() {
                 pixels.resize_surface(size.width, size.height);
             }
 
-            // Update internal state and request a redraw
-            cpu.emulate_cycle(&event, &mut stack, &mut vram, &mut ram);
-            window.request_redraw();
+            // F5 starts the ROM again, and with Shift or Control held, reads it from its file
+            // again first, as watching the file does whenever it changes
+            if let Some(reloader) = &mut reloader {
+                let hard = input.held_shift() || input.held_control();
+                let result = if input.key_pressed(VirtualKeyCode::F5) && hard {
+                    Some(reloader.reload(&mut machine))
+                } else if input.key_pressed(VirtualKeyCode::F5) {
+                    Some(reloader.reset(&mut machine))
+                } else {
+                    reloader.reload_if_changed(&mut machine)
+                };
+                match result {
+                    Some(Ok(info)) => {
+                        println!("Restarted ROM ({}).", info);
+                        crashed = false;
+                        window.request_redraw();
+                    }
+                    Some(Err(e)) => error!("Could not reload the ROM: {}", e),
+                    None => {}
+                }
+            }
+
+            // Speed controls, for keys not bound to the keypad
+            let shortcut = |key| !settings.keymap.is_bound(key) && input.key_pressed(key);
+            if shortcut(VirtualKeyCode::P) || shortcut(VirtualKeyCode::Pause) {
+                clock.toggle_pause();
+            }
+            if shortcut(VirtualKeyCode::N) {
+                clock.advance();
+            }
+            if shortcut(VirtualKeyCode::Minus) {
+                clock.slower();
+            }
+            if shortcut(VirtualKeyCode::Equals) {
+                clock.faster();
+            }
+            let tab = VirtualKeyCode::Tab;
+            clock.set_fast_forward(!settings.keymap.is_bound(tab) && input.key_held(tab));
+            let status = if crashed {
+                "Chip-8 (crashed)".to_string()
+            } else if clock.is_full_speed() {
+                "Chip-8".to_string()
+            } else {
+                format!("Chip-8 ({})", clock)
+            };
+            if status != title {
+                window.set_title(&status);
+                title = status;
+            }
+
+            // Update internal state. With a speed set, a whole frame's worth of instructions runs
+            // at once, and fast-forward runs several frames at once. Without one, instructions
+            // run one at a time, several at once when fast-forwarding, and a frame ends whenever
+            // its time is up
+            settings.keymap.update(&input, &mut machine.keypad);
+            let silent = machine.cpu.sound_timer == 0;
+            let now = Instant::now();
+            let ticks = if paced || crashed || !clock.running() {
+                0
+            } else {
+                clock.multiple() as usize
+            };
+            let frame_ticks = settings.ticks_per_frame.unwrap_or(0);
+            let mut result = run_ticks(&mut machine, &mut screen, ticks);
+            let frames = if crashed { 0 } else { clock.frames_due(now) };
+            for _ in 0..frames {
+                result = result.and_then(|()| run_ticks(&mut machine, &mut screen, frame_ticks));
+                if result.is_err() {
+                    break;
+                }
+                screen.end_frame(&mut machine);
+                if let Some(active) = &mut recorder {
+                    if let Err(e) = active.add_frame(&screen.image()) {
+                        error!("Could not record to {}: {}", active.path().display(), e);
+                        recorder = None;
+                    }
+                }
+            }
+            match result {
+                Ok(()) => hud.count(ticks + frames as usize * frame_ticks, frames),
+                Err(e) => {
+                    report_crash(&machine, &e);
+                    hud.set_error(&e, machine.cpu.program_counter);
+                    crashed = true;
+                    window.request_redraw();
+                }
+            }
+            let rates_changed = hud.update(now);
+            if frames > 0 || (rates_changed && hud.is_shown()) {
+                window.request_redraw();
+            }
+
+            // There's no sound device output, so a sound starting rings the terminal bell
+            if settings.audio && silent && machine.cpu.sound_timer != 0 {
+                print!("\x07");
+                let _ = io::stdout().flush();
+            }
+
+            // Sleep to the next frame, or to slow execution to a reasonable rate
+            let sleep = if crashed {
+                FRAME_TIME
+            } else if paced || !clock.running() {
+                clock.until_next_frame(Instant::now())
+            } else {
+                clock.slowed(Duration::from_micros(settings.cycle_sleep_us))
+            };
+            std::thread::sleep(sleep);
         }
     });
 }
+
+/// Run instructions, letting the screen see each one.
+fn run_ticks(
+    machine: &mut Machine,
+    screen: &mut Screen,
+    ticks: usize,
+) -> Result<(), EmulationError> {
+    for _ in 0..ticks {
+        screen.step(machine)?;
+    }
+    Ok(())
+}
+
+/// `--frames`: run the ROM for a number of frames without a window, recording each of them.
+fn record_headless(
+    mut machine: Machine,
+    mut screen: Screen,
+    mut recorder: Recorder,
+    ticks_per_frame: usize,
+    frames: usize,
+) -> Result<(), String> {
+    let mut result = Ok(());
+    'frames: for _ in 0..frames {
+        for _ in 0..ticks_per_frame {
+            if let Err(e) = screen.step(&mut machine) {
+                report_crash(&machine, &e);
+                result = Err(format!(
+                    "Stopped recording early, as the ROM crashed: {}",
+                    e
+                ));
+                break 'frames;
+            }
+        }
+        screen.end_frame(&mut machine);
+        recorder
+            .add_frame(&screen.image())
+            .map_err(|e| format!("Could not record to {}: {}", recorder.path().display(), e))?;
+    }
+    stop_recording(Some(recorder));
+    result
+}
+
+/// Finish a recording, if one's being made, and say where it went.
+fn stop_recording(recorder: Option<Recorder>) {
+    if let Some(recorder) = recorder {
+        let path = recorder.path().display().to_string();
+        match recorder.finish() {
+            Ok(frames) => println!("Recorded {} frames to {}", frames, path),
+            Err(e) => error!("Could not finish recording {}: {}", path, e),
+        }
+    }
+}
+
+/// Log an instruction's failure and write a crash report for it.
+fn report_crash(machine: &Machine, e: &EmulationError) {
+    error!("{} at {:#05X}", e, machine.cpu.program_counter);
+    match crash::write_report(machine, e) {
+        Ok(path) => eprintln!("Crash report written to {}", path.display()),
+        Err(e) => error!("Could not write crash report: {}", e),
+    }
+}
+
+/// Lay a config file's settings over the others, or stop if it couldn't be read.
+fn apply_config(path: &Path, settings: &mut Settings) {
+    let result = Layer::load(path).and_then(|layer| {
+        layer
+            .apply(settings)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    });
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Load the symbol file given with `--symbols`, or else the one the assembler wrote next to the
+/// ROM, if there is one.
+fn load_symbols(path: Option<&str>, rom: Option<&str>) -> Result<Symbols, String> {
+    if let Some(path) = path {
+        return Symbols::load(Path::new(path));
+    }
+    // Only a symbol file asked for by name has to load; one found next to the ROM is a bonus
+    match rom.map(|rom| Path::new(rom).with_extension("sym")) {
+        Some(path) if path.exists() => Ok(Symbols::load(&path).unwrap_or_else(|e| {
+            eprintln!("{} (carrying on without symbols)", e);
+            Symbols::default()
+        })),
+        _ => Ok(Symbols::default()),
+    }
+}
+
+/// `chip-8 disasm`: print a ROM as assembly.
+fn disassemble(
+    rom: &str,
+    mode: disasm::Mode,
+    symbols: Option<&str>,
+    load_address: u16,
+) -> Result<(), String> {
+    let bytes = fs::read(rom).map_err(|e| format!("Could not read {}: {}", rom, e))?;
+    if load_address as usize + bytes.len() > MEM_BYTES {
+        return Err(format!(
+            "{} is {} bytes, which doesn't fit in memory at {}",
+            rom,
+            bytes.len(),
+            Address(load_address)
+        ));
+    }
+    let symbols = load_symbols(symbols, Some(rom))?;
+    let listing = disasm::disassemble(&bytes, load_address, mode, &symbols);
+    // A failed write is almost always the output being piped into something like `head`
+    let _ = write!(io::stdout().lock(), "{}", listing);
+    Ok(())
+}
+
+/// `chip-8 asm`: assemble source into a ROM, with a symbol file for the debugger next to it.
+fn assemble(source: &str, output: &str) -> Result<(), String> {
+    let source = Path::new(source);
+    let program = if source.extension().is_some_and(|e| e == "8o") {
+        octo::compile_file(source)
+    } else {
+        asm::assemble_file(source, PROG_MEM_START as u16)
+    }
+    .map_err(|e| e.to_string())?;
+    let rom = program.rom;
+
+    let path = Path::new(output).with_extension("sym");
+    File::create(&path)
+        .and_then(|mut f| program.symbols.write(&mut f))
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    fs::write(output, &rom).map_err(|e| format!("Could not write {}: {}", output, e))?;
+    println!("Assembled {} bytes into {}", rom.len(), output);
+    Ok(())
+}
+
+/// `chip-8 list`: print the ROMs in directories and archives, with their sizes and hashes.
+fn list_roms(paths: &[String]) -> Result<(), String> {
+    for path in paths {
+        let path = Path::new(path);
+        if !path.is_dir() {
+            list_archive(path)?;
+            continue;
+        }
+        let read_error = |e: io::Error| format!("Could not read {}: {}", path.display(), e);
+        let mut files: Vec<_> = fs::read_dir(path)
+            .map_err(read_error)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()
+            .map_err(read_error)?;
+        files.sort();
+        for file in files {
+            if archive::kind(&file).is_some() {
+                list_archive(&file)?;
+            } else if archive::is_rom_name(&file.to_string_lossy()) {
+                let rom = fs::read(&file)
+                    .map_err(|e| format!("Could not read {}: {}", file.display(), e))?;
+                print_rom(&file.display().to_string(), &rom);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list_archive(path: &Path) -> Result<(), String> {
+    let name = path.display();
+    let kind = archive::kind(path).ok_or(format!("{} is not a directory or archive", name))?;
+    let files = archive::read_all(path, kind).map_err(|e| format!("{}: {}", name, e))?;
+    println!("{}", name);
+    for (entry, rom) in files {
+        match rom {
+            Ok(rom) => print_rom(&format!("  {}", entry), &rom),
+            Err(e) => println!("{:<40} {}", format!("  {}", entry), e),
+        }
+    }
+    Ok(())
+}
+
+fn print_rom(name: &str, rom: &[u8]) {
+    let info = RomInfo::new(rom, PROG_MEM_START as u16);
+    println!("{:<40} {:>5} bytes  {}", name, info.size, info.sha1);
+}
+
+/// `chip-8 info`: print what's known about a ROM, and what can be guessed from its code.
+fn show_info(rom: &str, entry: Option<&str>) -> Result<(), String> {
+    let path = Path::new(rom);
+    let bytes = if cartridge::is_cartridge(path) {
+        cartridge::load(path).map(|cart| cart.program.rom)
+    } else {
+        archive::read_rom_file(path, entry)
+    }
+    .map_err(|e| format!("Could not read {}: {}", rom, e))?;
+    let base = PROG_MEM_START as u16;
+    println!("{}: {}", rom, RomInfo::new(&bytes, base));
+
+    match romdb::lookup(&RomInfo::new(&bytes, base).sha1)? {
+        Some(entry) => println!("ROM database: {}", entry),
+        None => println!("ROM database: not found"),
+    }
+
+    let guess = detect::detect(&bytes, base);
+    println!(
+        "Detected platform: {} ({} confidence)",
+        guess.platform, guess.confidence
+    );
+    for reason in &guess.reasons {
+        println!("  {}", reason);
+    }
+    for addr in &guess.machine_calls {
+        println!("  machine code call at {:#05X}", addr);
+    }
+    for (name, on, reason) in &guess.quirks {
+        let setting = if *on { "on" } else { "off" };
+        println!("Suggested quirk: {}={} ({})", name, setting, reason);
+    }
+    Ok(())
+}
+
+/// Ask which file to load from an archive holding several ROMs, if there's someone at the
+/// terminal to answer. Otherwise loading the archive reports that an entry is needed.
+fn choose_entry(path: &Path) -> Option<String> {
+    let kind = archive::kind(path)?;
+    let entries = archive::entries(path, kind).ok()?;
+    if archive::default_entry(&entries).is_some() || !io::stdin().is_terminal() {
+        return None;
+    }
+    let choices = archive::candidates(&entries);
+    for (n, entry) in choices.iter().enumerate() {
+        println!("{:>3}  {:<32} {:>5} bytes", n + 1, entry.name, entry.size);
+    }
+    loop {
+        print!("Load which ROM? ");
+        io::stdout().flush().ok()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=choices.len()).contains(&n) => return Some(choices[n - 1].name.clone()),
+            _ => println!("Enter a number from 1 to {}", choices.len()),
+        }
+    }
+}
+
+/// End a command-line tool, reporting its error if it had one.
+fn exit_with(result: Result<(), String>) -> ! {
+    match result {
+        Ok(()) => process::exit(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
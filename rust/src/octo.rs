@@ -0,0 +1,954 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::asm::{AsmError, Program};
+use crate::instruction::Instruction;
+use crate::ram::{MEM_BYTES, PROG_MEM_START};
+use crate::symbols::{Entry, Symbols};
+
+// A macro that expands into itself would otherwise never finish
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+// The ordered comparisons work through VF, clobbering it
+const VF: u8 = 0xF;
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// How to patch a forward reference once its label is defined.
+enum Patch {
+    // The low 12 bits of the instruction at the address
+    Address,
+    // The whole 16-bit word at the address, for `i := long`
+    Word,
+    // The `vX := NN` bytes written by `:unpack`
+    UnpackHigh(u8),
+    UnpackLow,
+}
+
+struct Reference {
+    addr: usize,
+    patch: Patch,
+    name: String,
+    line: usize,
+}
+
+/// An open `begin` or `loop`, waiting for its `else`, `end` or `again`.
+enum Flow {
+    Begin {
+        jump: usize,
+        line: usize,
+    },
+    Else {
+        jump: usize,
+        line: usize,
+    },
+    Loop {
+        start: usize,
+        exits: Vec<usize>,
+        line: usize,
+    },
+}
+
+/// A comparison, compiled to whatever it needs to set up followed by a skip instruction.
+struct Condition {
+    setup: Vec<Instruction>,
+    skip_if_true: Instruction,
+    skip_if_false: Instruction,
+}
+
+/// Compile an Octo source file into a ROM loaded at 0x200.
+///
+/// This covers the language as the Octo IDE compiles it: labels, `:const`, `:alias`,
+/// `:unpack`, `:next`, `:org`, `:breakpoint`, `:macro`, `:calc` and `:byte`, the structured
+/// `if`/`begin`/`else`/`end` and `loop`/`while`/`again` forms, and the SCHIP and XO-CHIP
+/// instructions. As in Octo, execution starts at the `main` label.
+pub fn compile_file(path: &Path) -> Result<Program, AsmError> {
+    let file = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: file.clone(),
+        line: 0,
+        message: format!("Could not read: {}", e),
+    })?;
+    compile(&source, &file)
+}
+
+/// Compile Octo source that didn't come from a file, like a cartridge's. `file` is the name
+/// errors and the symbol table's source lines refer to it by.
+pub fn compile(source: &str, file: &str) -> Result<Program, AsmError> {
+    let mut compiler = Compiler {
+        file: file.to_string(),
+        tokens: tokenize(source),
+        line: 0,
+        rom: Vec::new(),
+        // The first two bytes are the jump to `main`, filled in at the end
+        here: PROG_MEM_START + 2,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        references: Vec::new(),
+        flow: Vec::new(),
+        next: None,
+        expansions: 0,
+        symbols: Symbols::default(),
+    };
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+    compiler.finish()
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(n, line)| {
+            let code = line.split('#').next().unwrap_or("");
+            code.split_whitespace().map(move |text| Token {
+                text: text.to_string(),
+                line: n + 1,
+            })
+        })
+        .collect()
+}
+
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    // The line of the token most recently taken, for error messages
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    references: Vec<Reference>,
+    flow: Vec<Flow>,
+    // A `:next` label, which names the second byte of the next instruction
+    next: Option<String>,
+    expansions: usize,
+    symbols: Symbols,
+}
+
+impl Compiler {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn token(&mut self) -> Result<String, AsmError> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or_else(|| self.error("Unexpected end of file"))?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|t| t.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.token()?;
+        if token != expected {
+            return Err(self.error(format!("Expected `{}`, found `{}`", expected, token)));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        // Macros expand in place, so the first token of the expansion is the real statement
+        while let Some(mac) = self.peek().and_then(|t| self.macros.get(t)) {
+            let params = mac.params.clone();
+            let body = mac.body.clone();
+            self.token()?;
+            self.expansions += 1;
+            if self.expansions > MAX_MACRO_EXPANSIONS {
+                return Err(self.error("Too many macro expansions (does a macro use itself?)"));
+            }
+            let mut args = HashMap::new();
+            for param in params {
+                args.insert(param, self.token()?);
+            }
+            for token in body.into_iter().rev() {
+                let text = args.get(&token.text).cloned().unwrap_or(token.text);
+                self.tokens.push_front(Token {
+                    text,
+                    line: token.line,
+                });
+            }
+        }
+
+        let token = self.token()?;
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(name, self.here)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.token()?;
+                let value = self.number(&token)?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let x = self.register()?;
+                self.aliases.insert(name, x);
+            }
+            ":unpack" => {
+                let nibble = self.value(0, 0xF, "nibble")? as u8;
+                let name = self.token()?;
+                let addr = self.address(&name, self.here + 1, Patch::UnpackHigh(nibble))?;
+                let high = (nibble << 4) | (addr >> 8) as u8;
+                self.emit(Instruction::SetRegister { x: 0, nn: high })?;
+                self.address(&name, self.here + 1, Patch::UnpackLow)?;
+                self.emit(Instruction::SetRegister {
+                    x: 1,
+                    nn: addr as u8,
+                })?;
+            }
+            ":next" => self.next = Some(self.name()?),
+            ":org" => {
+                let addr = self.value(PROG_MEM_START as i64, MEM_BYTES as i64 - 1, "address")?;
+                self.here = addr as usize;
+            }
+            ":breakpoint" => {
+                let name = self.token()?;
+                self.symbols.add(self.here as u16, Entry::Breakpoint(name));
+            }
+            // Monitors are an IDE feature; there is nothing to compile
+            ":monitor" => {
+                self.token()?;
+                self.token()?;
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+                while self.peek() != Some("{") {
+                    params.push(self.name()?);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name, Macro { params, body });
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let body = self.braced()?;
+                let value = self.calculate(&body)?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    let body = self.braced()?;
+                    self.calculate(&body)? as i64
+                } else {
+                    self.value(-128, 0xFF, "byte")?
+                };
+                self.emit_byte(value as u8)?;
+            }
+            ";" | "return" => self.emit(Instruction::Return)?,
+            "clear" => self.emit(Instruction::ClearScreen)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::BinaryCodedDecimal { x })?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    // XO-CHIP saves and loads an arbitrary range of registers
+                    self.token()?;
+                    let y = self.register()?;
+                    let low = if token == "save" { 0x2 } else { 0x3 };
+                    self.emit_word(0x5000 | (u16::from(x) << 8) | (u16::from(y) << 4) | low)?;
+                } else if token == "save" {
+                    self.emit(Instruction::StoreMemory { x })?;
+                } else {
+                    self.emit(Instruction::LoadMemory { x })?;
+                }
+            }
+            "saveflags" | "loadflags" => {
+                let x = self.register()?;
+                let low = if token == "saveflags" { 0x75 } else { 0x85 };
+                self.emit_word(0xF000 | (u16::from(x) << 8) | low)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.value(0, 0xF, "nibble")? as u8;
+                self.emit(Instruction::Draw { x, y, n })?;
+            }
+            "jump" | "jump0" | "native" => {
+                let name = self.token()?;
+                let nnn = self.address(&name, self.here, Patch::Address)? as u16;
+                self.emit(match token.as_str() {
+                    "jump" => Instruction::Jump { nnn },
+                    "jump0" => Instruction::JumpPlusV0 { nnn },
+                    _ => Instruction::MachineCall { nnn },
+                })?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                match token.as_str() {
+                    "delay" => self.emit(Instruction::SetDelay { x })?,
+                    "buzzer" => self.emit(Instruction::SetSound { x })?,
+                    _ => self.emit_word(0xF03A | (u16::from(x) << 8))?,
+                }
+            }
+            "i" => self.index()?,
+            "hires" => self.emit_word(0x00FF)?,
+            "lores" => self.emit_word(0x00FE)?,
+            "scroll-down" | "scroll-up" => {
+                let n = self.value(0, 0xF, "nibble")? as u16;
+                let high = if token == "scroll-down" {
+                    0x00C0
+                } else {
+                    0x00D0
+                };
+                self.emit_word(high | n)?;
+            }
+            "scroll-right" => self.emit_word(0x00FB)?,
+            "scroll-left" => self.emit_word(0x00FC)?,
+            "exit" => self.emit_word(0x00FD)?,
+            "plane" => {
+                let n = self.value(0, 0x3, "plane mask")? as u16;
+                self.emit_word(0xF001 | (n << 8))?;
+            }
+            "audio" => self.emit_word(0xF002)?,
+            "if" => {
+                let condition = self.condition()?;
+                match self.token()?.as_str() {
+                    "then" => {
+                        self.emit_condition(condition, false)?;
+                    }
+                    "begin" => {
+                        self.emit_condition(condition, true)?;
+                        let jump = self.here;
+                        self.emit(Instruction::Jump { nnn: 0 })?;
+                        self.flow.push(Flow::Begin {
+                            jump,
+                            line: self.line,
+                        });
+                    }
+                    other => {
+                        return Err(
+                            self.error(format!("Expected `then` or `begin`, found `{}`", other))
+                        )
+                    }
+                }
+            }
+            "else" => match self.flow.pop() {
+                Some(Flow::Begin { jump, line }) => {
+                    let end_jump = self.here;
+                    self.emit(Instruction::Jump { nnn: 0 })?;
+                    self.patch_jump(jump, self.here);
+                    self.flow.push(Flow::Else {
+                        jump: end_jump,
+                        line,
+                    });
+                }
+                _ => return Err(self.error("`else` without `begin`")),
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::Begin { jump, .. } | Flow::Else { jump, .. }) => {
+                    self.patch_jump(jump, self.here)
+                }
+                _ => return Err(self.error("`end` without `begin`")),
+            },
+            "loop" => self.flow.push(Flow::Loop {
+                start: self.here,
+                exits: Vec::new(),
+                line: self.line,
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_condition(condition, true)?;
+                let jump = self.here;
+                self.emit(Instruction::Jump { nnn: 0 })?;
+                match self
+                    .flow
+                    .iter_mut()
+                    .rev()
+                    .find(|f| matches!(f, Flow::Loop { .. }))
+                {
+                    Some(Flow::Loop { exits, .. }) => exits.push(jump),
+                    _ => return Err(self.error("`while` outside a loop")),
+                }
+            }
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop { start, exits, .. }) => {
+                    self.emit(Instruction::Jump { nnn: start as u16 })?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.here);
+                    }
+                }
+                _ => return Err(self.error("`again` without `loop`")),
+            },
+            _ if self.register_named(&token).is_some() => self.assignment(&token)?,
+            _ if token.starts_with(':') => {
+                return Err(self.error(format!("Unknown directive `{}`", token)))
+            }
+            // A bare number is a byte of data, and a bare name is a subroutine call
+            _ => match self.number(&token) {
+                Ok(value) => {
+                    let value = self.in_range(value as i64, -128, 0xFF, "byte")?;
+                    self.emit_byte(value as u8)?;
+                }
+                Err(_) => {
+                    let nnn = self.address(&token, self.here, Patch::Address)? as u16;
+                    self.emit(Instruction::Call { nnn })?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// `vX := ...`, `vX += ...` and the other register operators.
+    fn assignment(&mut self, target: &str) -> Result<(), AsmError> {
+        let x = self.register_named(target).unwrap();
+        let op = self.token()?;
+        let source = self.token()?;
+        let y = self.register_named(&source);
+
+        let instruction = match (op.as_str(), y) {
+            (":=", Some(y)) => Instruction::SetVxToVy { x, y },
+            (":=", None) => match source.as_str() {
+                "key" => Instruction::GetKey { x },
+                "delay" => Instruction::GetDelay { x },
+                "random" => Instruction::Random {
+                    x,
+                    nn: self.value(0, 0xFF, "byte")? as u8,
+                },
+                _ => Instruction::SetRegister {
+                    x,
+                    nn: self.byte(&source)?,
+                },
+            },
+            ("+=", Some(y)) => Instruction::AddWithCarry { x, y },
+            ("+=", None) => Instruction::Add {
+                x,
+                nn: self.byte(&source)?,
+            },
+            ("-=", Some(y)) => Instruction::SubtractVyFromVx { x, y },
+            ("-=", None) => Instruction::Add {
+                x,
+                nn: self.byte(&source)?.wrapping_neg(),
+            },
+            ("=-", Some(y)) => Instruction::SubtractVxFromVy { x, y },
+            ("|=", Some(y)) => Instruction::Or { x, y },
+            ("&=", Some(y)) => Instruction::And { x, y },
+            ("^=", Some(y)) => Instruction::Xor { x, y },
+            (">>=", Some(y)) => Instruction::ShiftRight { x, y },
+            ("<<=", Some(y)) => Instruction::ShiftLeft { x, y },
+            _ => {
+                return Err(self.error(format!("Invalid operation `{} {} {}`", target, op, source)))
+            }
+        };
+        self.emit(instruction)
+    }
+
+    /// `i := addr`, `i := long addr`, `i := hex vX`, `i := bighex vX` and `i += vX`.
+    fn index(&mut self) -> Result<(), AsmError> {
+        let op = self.token()?;
+        let source = self.token()?;
+        match (op.as_str(), source.as_str()) {
+            (":=", "hex") => {
+                let x = self.register()?;
+                self.emit(Instruction::FontCharacter { x })
+            }
+            (":=", "bighex") => {
+                let x = self.register()?;
+                self.emit_word(0xF030 | (u16::from(x) << 8))
+            }
+            (":=", "long") => {
+                let name = self.token()?;
+                let addr = self.address(&name, self.here + 2, Patch::Word)?;
+                self.emit_word(0xF000)?;
+                self.emit_byte((addr >> 8) as u8)?;
+                self.emit_byte(addr as u8)
+            }
+            (":=", _) => {
+                let nnn = self.address(&source, self.here, Patch::Address)? as u16;
+                self.emit(Instruction::SetIndex { nnn })
+            }
+            ("+=", _) => match self.register_named(&source) {
+                Some(x) => self.emit(Instruction::AddToIndex { x }),
+                None => Err(self.error("`i +=` takes a register")),
+            },
+            _ => Err(self.error(format!("Invalid operation `i {} {}`", op, source))),
+        }
+    }
+
+    /// Parse `vX == vY`, `vX != 5`, `vX key`, `vX < vY` and so on.
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.register()?;
+        let op = self.token()?;
+        match op.as_str() {
+            "key" | "-key" => {
+                let pressed = Instruction::SkipIfPressed { x };
+                let released = Instruction::SkipIfNotPressed { x };
+                let (skip_if_true, skip_if_false) = if op == "key" {
+                    (pressed, released)
+                } else {
+                    (released, pressed)
+                };
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    skip_if_true,
+                    skip_if_false,
+                });
+            }
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {}
+            _ => return Err(self.error(format!("Invalid comparison `{}`", op))),
+        }
+
+        let source = self.token()?;
+        let y = self.register_named(&source);
+        let (equal, not_equal) = match y {
+            Some(y) => (
+                Instruction::SkipIfEqReg { x, y },
+                Instruction::SkipIfNotEqReg { x, y },
+            ),
+            None => {
+                let nn = self.byte(&source)?;
+                (
+                    Instruction::SkipIfEq { x, nn },
+                    Instruction::SkipIfNotEq { x, nn },
+                )
+            }
+        };
+        match op.as_str() {
+            "==" => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    skip_if_true: equal,
+                    skip_if_false: not_equal,
+                })
+            }
+            "!=" => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    skip_if_true: not_equal,
+                    skip_if_false: equal,
+                })
+            }
+            _ => {}
+        }
+
+        // The ordered comparisons subtract through VF and test the borrow flag, like Octo does
+        let load = match (y, equal) {
+            (Some(y), _) => Instruction::SetVxToVy { x: VF, y },
+            (None, Instruction::SkipIfEq { nn, .. }) => Instruction::SetRegister { x: VF, nn },
+            _ => unreachable!(),
+        };
+        let (subtract, flag) = match op.as_str() {
+            // VF := vX - VF sets VF when vX >= the operand
+            ">=" => (Instruction::SubtractVxFromVy { x: VF, y: x }, 1),
+            "<" => (Instruction::SubtractVxFromVy { x: VF, y: x }, 0),
+            // VF := VF - vX sets VF when vX <= the operand
+            "<=" => (Instruction::SubtractVyFromVx { x: VF, y: x }, 1),
+            _ => (Instruction::SubtractVyFromVx { x: VF, y: x }, 0),
+        };
+        Ok(Condition {
+            setup: vec![load, subtract],
+            skip_if_true: Instruction::SkipIfEq { x: VF, nn: flag },
+            skip_if_false: Instruction::SkipIfNotEq { x: VF, nn: flag },
+        })
+    }
+
+    /// Emit a condition so the following instruction is skipped when it is false, or, for
+    /// `begin` and `while`, when it is true.
+    fn emit_condition(&mut self, condition: Condition, skip_if_true: bool) -> Result<(), AsmError> {
+        for instruction in condition.setup {
+            self.emit(instruction)?;
+        }
+        if skip_if_true {
+            self.emit(condition.skip_if_true)
+        } else {
+            self.emit(condition.skip_if_false)
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), AsmError> {
+        self.emit_word(instruction.encode())
+    }
+
+    fn emit_word(&mut self, word: u16) -> Result<(), AsmError> {
+        if let Some(name) = self.next.take() {
+            self.define_label(name, self.here + 1)?;
+        }
+        self.symbols.add(
+            self.here as u16,
+            Entry::Source {
+                file: self.file.clone(),
+                line: self.line,
+            },
+        );
+        let [high, low] = word.to_be_bytes();
+        self.emit_byte(high)?;
+        self.emit_byte(low)
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        if self.here >= MEM_BYTES {
+            return Err(self.error("Program doesn't fit in memory"));
+        }
+        self.write_byte(self.here, byte);
+        self.here += 1;
+        Ok(())
+    }
+
+    fn write_byte(&mut self, addr: usize, byte: u8) {
+        let offset = addr - PROG_MEM_START;
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+    }
+
+    /// Point the jump at `addr` to `target`.
+    fn patch_jump(&mut self, addr: usize, target: usize) {
+        let offset = addr - PROG_MEM_START;
+        self.rom[offset] = (self.rom[offset] & 0xF0) | (target >> 8) as u8;
+        self.rom[offset + 1] = target as u8;
+    }
+
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), AsmError> {
+        if self.labels.contains_key(&name) {
+            return Err(self.error(format!("`{}` is already defined", name)));
+        }
+        self.symbols.add(addr as u16, Entry::Label(name.clone()));
+        self.labels.insert(name, addr);
+        Ok(())
+    }
+
+    /// Take a name for something being defined.
+    fn name(&mut self) -> Result<String, AsmError> {
+        let name = self.token()?;
+        if self.register_named(&name).is_some() || self.number(&name).is_ok() || name == "{" {
+            return Err(self.error(format!("`{}` can't be used as a name", name)));
+        }
+        Ok(name)
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.token()?;
+        self.register_named(&token)
+            .ok_or_else(|| self.error(format!("Expected a register, found `{}`", token)))
+    }
+
+    fn register_named(&self, token: &str) -> Option<u8> {
+        if let Some(&x) = self.aliases.get(token) {
+            return Some(x);
+        }
+        match token.strip_prefix('v').or_else(|| token.strip_prefix('V')) {
+            Some(n) if n.len() == 1 => u8::from_str_radix(n, 16).ok(),
+            _ => None,
+        }
+    }
+
+    /// A number literal or constant.
+    fn number(&self, token: &str) -> Result<f64, AsmError> {
+        if let Some(&value) = self.constants.get(token) {
+            return Ok(value);
+        }
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            i64::from_str_radix(bin, 2).ok()
+        } else {
+            digits.parse().ok()
+        };
+        match value {
+            Some(v) if negative => Ok(-v as f64),
+            Some(v) => Ok(v as f64),
+            None => Err(self.error(format!("Expected a number, found `{}`", token))),
+        }
+    }
+
+    fn value(&mut self, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        let token = self.token()?;
+        let value = self.number(&token)?;
+        self.in_range(value as i64, min, max, what)
+    }
+
+    fn in_range(&self, value: i64, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        if !(min..=max).contains(&value) {
+            return Err(self.error(format!("{} doesn't fit in a {}", value, what)));
+        }
+        Ok(value)
+    }
+
+    /// A byte operand. Negative numbers count down from 0x100, as in Octo.
+    fn byte(&self, token: &str) -> Result<u8, AsmError> {
+        let value = self.number(token)? as i64;
+        Ok(self.in_range(value, -128, 0xFF, "byte")? as u8)
+    }
+
+    /// An address operand: a number, a constant or a label, which may not be defined yet. A
+    /// forward reference is patched at `addr` once the label turns up.
+    fn address(&mut self, token: &str, addr: usize, patch: Patch) -> Result<usize, AsmError> {
+        if let Some(&target) = self.labels.get(token) {
+            return Ok(target);
+        }
+        if let Ok(value) = self.number(token) {
+            let max = if matches!(patch, Patch::Word) {
+                0xFFFF
+            } else {
+                0xFFF
+            };
+            return Ok(self.in_range(value as i64, 0, max, "address")? as usize);
+        }
+        self.references.push(Reference {
+            addr,
+            patch,
+            name: token.to_string(),
+            line: self.line,
+        });
+        Ok(0)
+    }
+
+    /// Take the tokens between a `{` and its matching `}`.
+    fn braced(&mut self) -> Result<Vec<Token>, AsmError> {
+        self.expect("{")?;
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let line = self.line;
+            let text = self.token()?;
+            match text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(Token { text, line });
+        }
+    }
+
+    /// Evaluate a `:calc` expression. As in Octo there's no operator precedence: everything is
+    /// evaluated right to left unless parenthesized.
+    fn calculate(&self, tokens: &[Token]) -> Result<f64, AsmError> {
+        let tokens: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        let mut pos = 0;
+        let value = self.expression(&tokens, &mut pos)?;
+        if pos != tokens.len() {
+            return Err(self.error(format!("Unexpected `{}` in expression", tokens[pos])));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[&str], pos: &mut usize) -> Result<f64, AsmError> {
+        let lhs = self.term(tokens, pos)?;
+        let Some(&op) = tokens.get(*pos) else {
+            return Ok(lhs);
+        };
+        if op == ")" {
+            return Ok(lhs);
+        }
+        *pos += 1;
+        let rhs = self.expression(tokens, pos)?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        let truth = |t: bool| if t { 1.0 } else { 0.0 };
+        let shift = |f: fn(i64, u32) -> Option<i64>| {
+            let value = u32::try_from(b).ok().and_then(|b| f(a, b));
+            value
+                .map(|v| v as f64)
+                .ok_or_else(|| self.error(format!("Can't shift by {}", b)))
+        };
+        Ok(match op {
+            "/" | "%" if rhs == 0.0 => return Err(self.error("Division by zero")),
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => shift(i64::checked_shl)?,
+            ">>" => shift(i64::checked_shr)?,
+            "<" => truth(lhs < rhs),
+            ">" => truth(lhs > rhs),
+            "<=" => truth(lhs <= rhs),
+            ">=" => truth(lhs >= rhs),
+            "==" => truth(lhs == rhs),
+            "!=" => truth(lhs != rhs),
+            _ => return Err(self.error(format!("Unknown operator `{}`", op))),
+        })
+    }
+
+    fn term(&self, tokens: &[&str], pos: &mut usize) -> Result<f64, AsmError> {
+        let token = *tokens
+            .get(*pos)
+            .ok_or_else(|| self.error("Expression ends too soon"))?;
+        *pos += 1;
+        let unary = |f: fn(f64) -> f64, pos: &mut usize| self.term(tokens, pos).map(f);
+        match token {
+            "(" => {
+                let value = self.expression(tokens, pos)?;
+                if tokens.get(*pos) != Some(&")") {
+                    return Err(self.error("Missing `)` in expression"));
+                }
+                *pos += 1;
+                Ok(value)
+            }
+            "-" => unary(|v| -v, pos),
+            "~" => unary(|v| !(v as i64) as f64, pos),
+            "!" => unary(|v| if v == 0.0 { 1.0 } else { 0.0 }, pos),
+            "sin" => unary(f64::sin, pos),
+            "cos" => unary(f64::cos, pos),
+            "tan" => unary(f64::tan, pos),
+            "exp" => unary(f64::exp, pos),
+            "log" => unary(f64::ln, pos),
+            "abs" => unary(f64::abs, pos),
+            "sqrt" => unary(f64::sqrt, pos),
+            "sign" => unary(f64::signum, pos),
+            "ceil" => unary(f64::ceil, pos),
+            "floor" => unary(f64::floor, pos),
+            "@" => {
+                let addr = self.term(tokens, pos)? as usize;
+                let byte = addr
+                    .checked_sub(PROG_MEM_START)
+                    .and_then(|offset| self.rom.get(offset));
+                Ok(byte.copied().unwrap_or(0) as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => match self.labels.get(token) {
+                Some(&addr) => Ok(addr as f64),
+                None => self.number(token),
+            },
+        }
+    }
+
+    /// Check everything was closed, fill in forward references and the jump to `main`.
+    fn finish(mut self) -> Result<Program, AsmError> {
+        if let Some(Flow::Begin { line, .. } | Flow::Else { line, .. }) = self.flow.last() {
+            self.line = *line;
+            return Err(self.error("`begin` without `end`"));
+        }
+        if let Some(Flow::Loop { line, .. }) = self.flow.last() {
+            self.line = *line;
+            return Err(self.error("`loop` without `again`"));
+        }
+
+        for reference in std::mem::take(&mut self.references) {
+            self.line = reference.line;
+            let target = *self
+                .labels
+                .get(&reference.name)
+                .ok_or_else(|| self.error(format!("Undefined name `{}`", reference.name)))?;
+            match reference.patch {
+                Patch::Address => self.patch_jump(reference.addr, target),
+                Patch::Word => {
+                    self.write_byte(reference.addr, (target >> 8) as u8);
+                    self.write_byte(reference.addr + 1, target as u8);
+                }
+                Patch::UnpackHigh(nibble) => {
+                    self.write_byte(reference.addr, (nibble << 4) | (target >> 8) as u8)
+                }
+                Patch::UnpackLow => self.write_byte(reference.addr, target as u8),
+            }
+        }
+
+        let main = *self.labels.get("main").ok_or_else(|| AsmError {
+            file: self.file.clone(),
+            line: 0,
+            message: "This program has no `: main` label".to_string(),
+        })?;
+        let jump = Instruction::Jump { nnn: main as u16 }
+            .encode()
+            .to_be_bytes();
+        self.write_byte(PROG_MEM_START, jump[0]);
+        self.write_byte(PROG_MEM_START + 1, jump[1]);
+
+        Ok(Program {
+            rom: self.rom,
+            symbols: self.symbols,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compile;
+    use crate::machine::Machine;
+    use crate::ram::PROG_MEM_START;
+
+    // Run a compiled program until it runs off its end, returning V1
+    fn run(source: &str) -> u8 {
+        let program = compile(source, "test.8o").unwrap();
+        let mut machine = Machine::new();
+        machine
+            .ram
+            .load_rom_bytes(&program.rom, PROG_MEM_START as u16)
+            .unwrap();
+        let end = (PROG_MEM_START + program.rom.len()) as u16;
+        while machine.cpu.program_counter < end {
+            machine.step().unwrap();
+        }
+        machine.cpu.general_registers[1]
+    }
+
+    #[test]
+    fn ordered_comparisons() {
+        type Compare = fn(u8, u8) -> bool;
+        let ops: [(&str, Compare); 4] = [
+            ("<", |a, b| a < b),
+            (">", |a, b| a > b),
+            ("<=", |a, b| a <= b),
+            (">=", |a, b| a >= b),
+        ];
+        for (op, expected) in ops {
+            for (a, b) in [(3, 5), (5, 5), (7, 5), (0, 255), (255, 0)] {
+                let want = u8::from(expected(a, b));
+                let with_register = format!(
+                    ": main v0 := {} v2 := {} v1 := 0 if v0 {} v2 then v1 := 1",
+                    a, b, op
+                );
+                assert_eq!(run(&with_register), want, "{} {} {}", a, op, b);
+                let with_constant =
+                    format!(": main v0 := {} v1 := 0 if v0 {} {} then v1 := 1", a, op, b);
+                assert_eq!(run(&with_constant), want, "{} {} {}", a, op, b);
+            }
+        }
+    }
+
+    #[test]
+    fn calc_rejects_bad_shifts_and_division_by_zero() {
+        let calc = |expression: &str| {
+            let source = format!(": main :calc X {{ {} }} v0 := X", expression);
+            // Past the jump to main, V0 := X's second byte is X
+            compile(&source, "test.8o").map(|program| program.rom[3])
+        };
+        assert_eq!(calc("1 << 3").unwrap(), 8);
+        assert_eq!(calc("64 >> 2").unwrap(), 16);
+        for bad in ["1 << 70", "1 >> -1", "1 / 0", "1 % 0"] {
+            assert!(calc(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+pub type Rgb = [u8; 3];
+
+/// The colors the display is drawn in. XO-CHIP draws on two planes, so it has a color for a
+/// pixel on in each and one for a pixel on in both; the names are Octo's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub background: Rgb,
+    // The first plane, which is the only one CHIP-8 and SCHIP have
+    pub fill: Rgb,
+    pub fill2: Rgb,
+    pub blend: Rgb,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::preset("mono").expect("mono is a preset")
+    }
+}
+
+/// The names of the built-in palettes.
+pub const PRESETS: &[&str] = &[
+    "mono", "green", "amber", "lcd", "octo", "hotdog", "gray", "cga0", "cga1",
+];
+
+impl Palette {
+    /// A built-in palette: white on black, the green and amber of phosphor monitors, a
+    /// greenish LCD, and Octo's XO-CHIP color sets.
+    pub fn preset(name: &str) -> Option<Palette> {
+        let [background, fill, fill2, blend] = match name {
+            "mono" => [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+            "green" => [0x041004, 0x33FF33, 0x1A801A, 0x0D400D],
+            "amber" => [0x140C00, 0xFFB000, 0x805800, 0x402C00],
+            "lcd" => [0xF9FFB3, 0x3D8026, 0xABCC47, 0x00131A],
+            "octo" => [0x996600, 0xFFCC00, 0xFF6600, 0x662200],
+            "hotdog" => [0x000000, 0xFF0000, 0xFFFF00, 0xFFFFFF],
+            "gray" => [0xAAAAAA, 0x000000, 0xFFFFFF, 0x666666],
+            "cga0" => [0x000000, 0x00FF00, 0xFF0000, 0xFFFF00],
+            "cga1" => [0x000000, 0xFF00FF, 0x00FFFF, 0xFFFFFF],
+            _ => return None,
+        }
+        .map(rgb);
+        Some(Palette {
+            background,
+            fill,
+            fill2,
+            blend,
+        })
+    }
+
+    /// The RGBA color of a pixel lit to `level`, from the background at 0 to the fill at 255.
+    pub fn pixel(&self, level: u8) -> [u8; 4] {
+        let mix = |bg: u8, fill: u8| {
+            let level = level as u32;
+            ((bg as u32 * (255 - level) + fill as u32 * level) / 255) as u8
+        };
+        let [r, g, b] = [0, 1, 2].map(|i| mix(self.background[i], self.fill[i]));
+        [r, g, b, 255]
+    }
+}
+
+pub fn parse_preset(name: &str) -> Result<Palette, String> {
+    Palette::preset(name).ok_or(format!(
+        "Unknown palette `{}` (expected {})",
+        name,
+        PRESETS.join(", ")
+    ))
+}
+
+fn rgb(color: u32) -> Rgb {
+    let [_, r, g, b] = color.to_be_bytes();
+    [r, g, b]
+}
+
+/// Parse a color written the HTML way, like `#FFCC00`.
+pub fn parse_color(s: &str) -> Result<Rgb, String> {
+    let invalid = || format!("Invalid color `{}` (expected #RRGGBB)", s);
+    let digits = s.strip_prefix('#').unwrap_or(s);
+    if digits.len() != 6 {
+        return Err(invalid());
+    }
+    u32::from_str_radix(digits, 16)
+        .map(rgb)
+        .map_err(|_| invalid())
+}
+
+/// Write a color the way `parse_color` reads it.
+pub fn format_color([r, g, b]: Rgb) -> String {
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
@@ -0,0 +1,38 @@
+use crate::{Vram, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// How brightly each pixel shows. A lit pixel is at full brightness, and an erased one fades
+/// out over a few frames like the phosphor of an old screen, rather than going dark at once.
+/// ROMs that erase a sprite and draw it again somewhere else then don't flicker so much.
+pub struct Phosphor {
+    levels: [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    // How much an erased pixel dims each frame
+    step: u8,
+}
+
+impl Phosphor {
+    /// Pixels that fade out over `frames` frames once erased. With none, they go dark at once.
+    pub fn new(frames: u32) -> Phosphor {
+        Phosphor {
+            levels: [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            step: (255 / frames.saturating_add(1)).max(1) as u8,
+        }
+    }
+
+    /// Catch up with the VRAM for a new frame.
+    pub fn update(&mut self, vram: &Vram) {
+        for (levels, row) in self.levels.iter_mut().zip(vram) {
+            for (level, &pixel) in levels.iter_mut().zip(row) {
+                *level = if pixel != 0 {
+                    255
+                } else {
+                    level.saturating_sub(self.step)
+                };
+            }
+        }
+    }
+
+    /// Each pixel's brightness, row by row from the top left.
+    pub fn levels(&self) -> impl Iterator<Item = u8> + '_ {
+        self.levels.iter().flatten().copied()
+    }
+}
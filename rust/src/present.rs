@@ -0,0 +1,129 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::delta::{Change, Delta};
+use crate::instruction::Instruction;
+use crate::machine::Machine;
+use crate::ram::Address;
+use crate::{Vram, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// How each frame's picture is made from the VRAM. ROMs move a sprite by XORing it off and
+/// drawing it again, so the VRAM at the end of a frame can be missing sprites that were
+/// half-way through being moved, which shows as flicker. All of these only change what's
+/// shown; the VRAM itself is left as the ROM drew it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presentation {
+    /// The VRAM as it is at the end of the frame.
+    Direct,
+    /// The screen as it was just before the ROM last cleared it, if it cleared it this frame,
+    /// so a ROM that redraws everything every frame is only seen once it's finished. Otherwise,
+    /// the VRAM at the end of the frame, where the VIP's drawing waited for the display.
+    Clear,
+    /// The VRAM at the end of this frame and the last ORed together.
+    Blend,
+    /// Pixels turned off during the frame still show until the next one.
+    Lazy,
+}
+
+/// Makes each frame's picture from the VRAM, in one of the presentation modes.
+pub struct Presenter {
+    mode: Presentation,
+    picture: Vram,
+    // The VRAM at the end of the last frame, for blending
+    last: Vram,
+    // The screen just before the ROM cleared it this frame, if it did
+    before_clear: Option<Vram>,
+    // Pixels turned off this frame
+    erased: Vram,
+}
+
+impl Presenter {
+    pub fn new(mode: Presentation) -> Presenter {
+        let blank = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        Presenter {
+            mode,
+            picture: blank,
+            last: blank,
+            before_clear: None,
+            erased: blank,
+        }
+    }
+
+    /// Look at the instruction about to run, before `Machine::step` runs it.
+    pub fn before_step(&mut self, machine: &Machine) {
+        if self.mode != Presentation::Clear {
+            return;
+        }
+        let opcode = machine.ram.read_word(Address(machine.cpu.program_counter));
+        if let Ok(opcode) = opcode {
+            if Instruction::decode(opcode) == Some(Instruction::ClearScreen) {
+                self.before_clear = Some(machine.vram);
+            }
+        }
+    }
+
+    /// Note the pixels an instruction turned off, after it's run.
+    pub fn after_step(&mut self, delta: &Delta, vram: &Vram) {
+        if self.mode != Presentation::Lazy {
+            return;
+        }
+        for change in delta.changes() {
+            if let Change::Pixel { x, y } = *change {
+                if vram[y][x] == 0 {
+                    self.erased[y][x] = 1;
+                }
+            }
+        }
+    }
+
+    /// Make the picture for the frame that's just ended.
+    pub fn end_frame(&mut self, vram: &Vram) -> &Vram {
+        self.picture = match self.mode {
+            Presentation::Direct => *vram,
+            Presentation::Clear => self.before_clear.take().unwrap_or(*vram),
+            Presentation::Blend => or(vram, &self.last),
+            Presentation::Lazy => or(vram, &self.erased),
+        };
+        self.last = *vram;
+        self.erased = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        &self.picture
+    }
+}
+
+fn or(a: &Vram, b: &Vram) -> Vram {
+    let mut out = *a;
+    for (row, other) in out.iter_mut().zip(b) {
+        for (pixel, &on) in row.iter_mut().zip(other) {
+            *pixel |= on;
+        }
+    }
+    out
+}
+
+impl FromStr for Presentation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Presentation, String> {
+        match s {
+            "direct" => Ok(Presentation::Direct),
+            "clear" => Ok(Presentation::Clear),
+            "blend" => Ok(Presentation::Blend),
+            "lazy" => Ok(Presentation::Lazy),
+            _ => Err(format!(
+                "Unknown presentation `{}` (expected direct, clear, blend or lazy)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Presentation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Presentation::Direct => write!(f, "direct"),
+            Presentation::Clear => write!(f, "clear"),
+            Presentation::Blend => write!(f, "blend"),
+            Presentation::Lazy => write!(f, "lazy"),
+        }
+    }
+}
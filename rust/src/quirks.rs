@@ -0,0 +1,125 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Behaviors that differ between CHIP-8 interpreters, which ROMs written for one of them rely
+/// on. The names and meanings follow Octo's options. The defaults are mostly SCHIP's: shifts
+/// and loads and stores work its way, but BNNN adds V0 and sprites wrap around the edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VX in place, rather than shifting VY into VX.
+    pub shift: bool,
+    /// FX55 and FX65 leave I alone, rather than advancing it past the registers.
+    pub load_store: bool,
+    /// BNNN jumps to NNN plus VX (where X is NNN's top digit), rather than plus V0.
+    pub jump: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub logic: bool,
+    /// Sprites are clipped at the edges of the screen, rather than wrapping around.
+    pub clip: bool,
+    /// 8XY4 to 8XYE set VF before VX rather than after, so when VX is VF it's left holding the
+    /// result rather than the flag.
+    pub vf_order: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift: true,
+            load_store: true,
+            jump: false,
+            logic: false,
+            clip: false,
+            vf_order: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// Turn a quirk on or off by the name it has in the ROM database and on the command line.
+    pub fn set(&mut self, name: &str, on: bool) -> Result<(), String> {
+        let quirk = match name {
+            "shift" => &mut self.shift,
+            "load-store" => &mut self.load_store,
+            "jump" => &mut self.jump,
+            "logic" => &mut self.logic,
+            "clip" => &mut self.clip,
+            "vf-order" => &mut self.vf_order,
+            _ => {
+                return Err(format!(
+                "Unknown quirk `{}` (expected shift, load-store, jump, logic, clip or vf-order)",
+                name
+            ))
+            }
+        };
+        *quirk = on;
+        Ok(())
+    }
+}
+
+/// The interpreters ROMs are written for, each with its own quirks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// The COSMAC VIP's original interpreter.
+    Chip8,
+    /// SUPER-CHIP 1.1 on the HP 48.
+    Schip,
+    /// Octo's extended CHIP-8.
+    XoChip,
+}
+
+impl Platform {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks {
+                shift: false,
+                load_store: false,
+                jump: false,
+                logic: true,
+                clip: true,
+                vf_order: false,
+            },
+            Platform::Schip => Quirks {
+                shift: true,
+                load_store: true,
+                jump: true,
+                logic: false,
+                clip: true,
+                vf_order: false,
+            },
+            Platform::XoChip => Quirks {
+                shift: false,
+                load_store: false,
+                jump: false,
+                logic: false,
+                clip: false,
+                vf_order: false,
+            },
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Platform, String> {
+        match s {
+            "chip8" => Ok(Platform::Chip8),
+            "schip" => Ok(Platform::Schip),
+            "xochip" => Ok(Platform::XoChip),
+            _ => Err(format!(
+                "Unknown platform `{}` (expected chip8, schip or xochip)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::Schip => write!(f, "SCHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
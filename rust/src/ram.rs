@@ -1,9 +1,20 @@
+use std::fmt;
 use std::io::Read;
-use std::vec;
+use std::path::Path;
+use std::str::FromStr;
 
-const MEM_BYTES: usize = 4096;
+use crate::archive;
+use crate::error::EmulationError;
+use crate::rom::RomInfo;
+
+pub const MEM_BYTES: usize = 4096;
 pub const PROG_MEM_START: usize = 0x200;
-const FONT_COUNT: usize = 80;
+pub const FONT_COUNT: usize = 80;
+
+// The VIP kept its stack, its own variables and the display buffer at the top of memory
+const VIP_STACK_START: u16 = 0xEA0;
+const VIP_VARIABLES_START: u16 = 0xED0;
+const VIP_DISPLAY_START: u16 = 0xF00;
 
 const FONT_SET: [u8; FONT_COUNT] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -24,64 +35,435 @@ const FONT_SET: [u8; FONT_COUNT] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
-pub struct RAM {
-    memory: Vec<u8>,
+/// An address in the emulated address space. It can point past the end of memory; `RAM`
+/// decides what happens when it's used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address(pub u16);
+
+impl Address {
+    /// The address `n` bytes on, unless that's past the top of the address space.
+    pub fn offset(self, n: u16) -> Option<Address> {
+        self.0.checked_add(n).map(Address)
+    }
 }
 
-impl RAM {
-    pub fn set_memory(&mut self, val: u8, index: usize) -> Result<(), String> {
-        if index > MEM_BYTES {
-            Err("Memory index out of bounds".to_string())
-        } else {
-            self.memory.insert(index, val);
-            Ok(())
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#05X}", self.0)
+    }
+}
+
+/// What happens to an access past the top of memory, such as FX33 or DXYN with I near 0xFFF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryPolicy {
+    /// Fault with `MemoryOutOfBounds`.
+    Error,
+    /// Wrap around to the bottom of memory, as the VIP's 12-bit addressing did.
+    Wrap,
+}
+
+impl FromStr for MemoryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MemoryPolicy, String> {
+        match s {
+            "error" => Ok(MemoryPolicy::Error),
+            "wrap" => Ok(MemoryPolicy::Wrap),
+            _ => Err(format!(
+                "Unknown memory policy `{}` (expected error or wrap)",
+                s
+            )),
         }
     }
+}
+
+/// What happens when the ROM writes to a region it isn't allowed to, such as the font.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    /// Fault with `ProtectedWrite`.
+    Error,
+    /// Log a warning and let the write through.
+    Warn,
+    /// Don't check writes at all.
+    Off,
+}
+
+impl FromStr for Protection {
+    type Err = String;
 
-    pub fn read_memory(&self, addr: usize) -> u8 {
-        *self.memory.get(addr).unwrap()
+    fn from_str(s: &str) -> Result<Protection, String> {
+        match s {
+            "error" => Ok(Protection::Error),
+            "warn" => Ok(Protection::Warn),
+            "off" => Ok(Protection::Off),
+            _ => Err(format!(
+                "Unknown protection `{}` (expected error, warn or off)",
+                s
+            )),
+        }
     }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryConfig {
+    pub policy: MemoryPolicy,
+    pub protection: Protection,
+    // Where the font is loaded. The C implementation keeps it at 0x50, as many interpreters do
+    pub font_base: u16,
+    // Reserve the VIP's stack, interpreter variables and display buffer at 0xEA0-0xFFF
+    pub vip_layout: bool,
+}
 
-    pub fn get_instruction(&self, addr: u16) -> u16 {
-        (u16::from(self.read_memory(addr as usize)) << 8)
-            | u16::from(self.read_memory((addr as usize) + 1))
+impl Default for MemoryConfig {
+    fn default() -> MemoryConfig {
+        MemoryConfig {
+            policy: MemoryPolicy::Error,
+            protection: Protection::Warn,
+            font_base: 0,
+            vip_layout: false,
+        }
     }
+}
 
-    pub fn new() -> RAM {
+/// Whether the ROM may write to a region. The interpreter itself can write anywhere.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// A named range of the address space.
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub name: &'static str,
+    pub start: u16,
+    // Exclusive
+    pub end: u16,
+    pub access: Access,
+}
+
+impl Region {
+    pub fn contains(&self, index: usize) -> bool {
+        (self.start as usize..self.end as usize).contains(&index)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct RAM {
+    memory: [u8; MEM_BYTES],
+    config: MemoryConfig,
+    // Most specific first, so the font is found before the interpreter area around it
+    regions: Vec<Region>,
+}
+
+impl RAM {
+    /// Memory with the font loaded. `config.font_base` must leave room for the font below the
+    /// program.
+    pub fn new(config: MemoryConfig) -> RAM {
+        assert!(config.font_base as usize + FONT_COUNT <= PROG_MEM_START);
         let mut r = RAM {
-            memory: vec![0; MEM_BYTES],
+            memory: [0; MEM_BYTES],
+            config,
+            regions: memory_map(&config),
         };
 
         r.load_font();
         r
     }
 
-    pub fn load_rom(&mut self, mut f: std::fs::File) {
-        let mut buffer = [0; MEM_BYTES];
-        f.read(&mut buffer).unwrap();
-
-        let mut i: usize = 0;
-        for pos in PROG_MEM_START..MEM_BYTES {
-            let mem_val = *buffer.get(i).unwrap();
-            match self.set_memory(mem_val, pos) {
-                Ok(_) => (),
-                Err(e) => println!(
-                    "Could not set ROM into memory. Data may be corrupted. Error: {:?}",
-                    e
-                ),
-            };
-            i += 1;
+    pub fn config(&self) -> MemoryConfig {
+        self.config
+    }
+
+    /// The regions the address space is divided into, most specific first.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// The region a resolved address falls in.
+    pub fn region(&self, index: usize) -> Option<&Region> {
+        self.regions.iter().find(|r| r.contains(index))
+    }
+
+    /// Where the sprite for a hex digit starts, as pointed to by FX29.
+    pub fn font_address(&self, digit: u8) -> u16 {
+        self.config.font_base + u16::from(digit) * 5
+    }
+
+    /// Check that the ROM is allowed to write to `addr`, faulting or warning if it isn't as
+    /// the protection setting says. Returns where the write lands.
+    pub fn check_write(&self, addr: Address) -> Result<usize, EmulationError> {
+        let index = self.resolve(addr)?;
+        if self.config.protection == Protection::Off {
+            return Ok(index);
+        }
+        match self.region(index) {
+            Some(region) if region.access == Access::ReadOnly => {
+                if self.config.protection == Protection::Error {
+                    return Err(EmulationError::ProtectedWrite(addr, region.name));
+                }
+                log::warn!("Write to the read-only {} region at {}", region.name, addr);
+                Ok(index)
+            }
+            _ => Ok(index),
+        }
+    }
+
+    /// Where `addr` actually lands in memory, under the memory policy.
+    pub fn resolve(&self, addr: Address) -> Result<usize, EmulationError> {
+        let index = addr.0 as usize;
+        match self.config.policy {
+            _ if index < MEM_BYTES => Ok(index),
+            MemoryPolicy::Wrap => Ok(index % MEM_BYTES),
+            MemoryPolicy::Error => Err(EmulationError::MemoryOutOfBounds(addr)),
+        }
+    }
+
+    /// The address `n` bytes past `addr`. Running past the top of the address space is out of
+    /// bounds, unless the memory policy wraps it around, in which case it lands in memory.
+    pub fn offset(&self, addr: Address, n: u16) -> Result<Address, EmulationError> {
+        match self.config.policy {
+            MemoryPolicy::Wrap => Ok(Address(addr.0.wrapping_add(n) % MEM_BYTES as u16)),
+            MemoryPolicy::Error => addr
+                .offset(n)
+                .ok_or(EmulationError::MemoryOutOfBounds(addr)),
+        }
+    }
+
+    pub fn read(&self, addr: Address) -> Result<u8, EmulationError> {
+        Ok(self.memory[self.resolve(addr)?])
+    }
+
+    pub fn write(&mut self, addr: Address, val: u8) -> Result<(), EmulationError> {
+        self.memory[self.resolve(addr)?] = val;
+        Ok(())
+    }
+
+    /// Read the big-endian word at `addr`, such as an instruction.
+    pub fn read_word(&self, addr: Address) -> Result<u16, EmulationError> {
+        Ok(u16::from_be_bytes([
+            self.read(addr)?,
+            self.read(self.offset(addr, 1)?)?,
+        ]))
+    }
+
+    /// The whole address space, for snapshots and memory dumps.
+    pub fn bytes(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Copy `data` into memory starting at `start`, which it must fit above.
+    pub fn load(&mut self, start: usize, data: &[u8]) -> Result<(), String> {
+        let room = MEM_BYTES.saturating_sub(start);
+        if data.len() > room {
+            return Err(format!(
+                "{} bytes don't fit in memory at {:#05X} ({} bytes free)",
+                data.len(),
+                start,
+                room
+            ));
         }
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Load a ROM at `addr`, which must be in program memory with room for all of it.
+    pub fn load_rom_bytes(&mut self, rom: &[u8], addr: u16) -> Result<RomInfo, String> {
+        let region = self
+            .region(addr as usize)
+            .filter(|r| r.access == Access::ReadWrite)
+            .ok_or(format!(
+                "Load address {} is outside program memory",
+                Address(addr)
+            ))?;
+        let room = (region.end - addr) as usize;
+        if rom.is_empty() {
+            return Err("The ROM is empty".to_string());
+        }
+        if rom.len() > room {
+            return Err(format!(
+                "The ROM is {} bytes, but only {} fit in program memory at {}",
+                rom.len(),
+                room,
+                Address(addr)
+            ));
+        }
+        // Instructions are two bytes, but plenty of ROMs end in an odd byte of data, such as the
+        // 1301-byte roms/invaders.ch8, so this only looks suspicious rather than wrong
+        if !rom.len().is_multiple_of(2) {
+            log::warn!(
+                "The ROM is an odd number of bytes ({}), so it may be truncated",
+                rom.len()
+            );
+        }
+        self.load(addr as usize, rom)?;
+        Ok(RomInfo::new(rom, addr))
+    }
+
+    /// Read a ROM to the end and load it at `addr`, like `load_rom_bytes`.
+    pub fn load_rom_reader(&mut self, mut reader: impl Read, addr: u16) -> Result<RomInfo, String> {
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom).map_err(|e| e.to_string())?;
+        self.load_rom_bytes(&rom, addr)
+    }
+
+    /// The bytes of a ROM that's been loaded, as they are now.
+    pub fn rom(&self, info: &RomInfo) -> &[u8] {
+        let start = info.load_address as usize;
+        &self.memory[start..start + info.size]
+    }
+
+    /// Load a ROM file at `addr`, like `load_rom_bytes`. Zip and gzip archives are opened, and
+    /// `entry` picks the file to load from them; it can be left out if there's only one ROM.
+    pub fn load_rom_file(
+        &mut self,
+        path: &Path,
+        entry: Option<&str>,
+        addr: u16,
+    ) -> Result<RomInfo, String> {
+        let rom = archive::read_rom_file(path, entry)?;
+        self.load_rom_bytes(&rom, addr)
     }
 
     fn load_font(&mut self) {
-        let mut i = 0;
-        for c in FONT_SET {
-            match self.set_memory(c, i) {
-                Ok(_) => continue,
-                Err(e) => println!("Encountered error {:?} while loading font.", e),
-            };
-            i += 1;
+        let base = self.config.font_base as usize;
+        self.memory[base..base + FONT_COUNT].copy_from_slice(&FONT_SET);
+    }
+}
+
+fn memory_map(config: &MemoryConfig) -> Vec<Region> {
+    let region = |name, start, end, access| Region {
+        name,
+        start,
+        end,
+        access,
+    };
+    let font_end = config.font_base + FONT_COUNT as u16;
+    let mut regions = vec![
+        region("font", config.font_base, font_end, Access::ReadOnly),
+        region("interpreter", 0, PROG_MEM_START as u16, Access::ReadOnly),
+    ];
+    if config.vip_layout {
+        regions.extend([
+            region(
+                "program",
+                PROG_MEM_START as u16,
+                VIP_STACK_START,
+                Access::ReadWrite,
+            ),
+            region(
+                "stack",
+                VIP_STACK_START,
+                VIP_VARIABLES_START,
+                Access::ReadOnly,
+            ),
+            region(
+                "variables",
+                VIP_VARIABLES_START,
+                VIP_DISPLAY_START,
+                Access::ReadOnly,
+            ),
+            region(
+                "display",
+                VIP_DISPLAY_START,
+                MEM_BYTES as u16,
+                Access::ReadOnly,
+            ),
+        ]);
+    } else {
+        regions.push(region(
+            "program",
+            PROG_MEM_START as u16,
+            MEM_BYTES as u16,
+            Access::ReadWrite,
+        ));
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(policy: MemoryPolicy, protection: Protection) -> RAM {
+        RAM::new(MemoryConfig {
+            policy,
+            protection,
+            ..MemoryConfig::default()
+        })
+    }
+
+    #[test]
+    fn accesses_past_the_top_of_memory_follow_the_policy() {
+        let mut ram = memory(MemoryPolicy::Wrap, Protection::Off);
+        ram.write(Address(0x1203), 0xAB).unwrap();
+        assert_eq!(ram.read(Address(0x203)), Ok(0xAB));
+        assert_eq!(ram.resolve(Address(0xFFFF)), Ok(0xFFF));
+        assert_eq!(ram.offset(Address(0xFFF), 1), Ok(Address(0)));
+
+        let mut ram = memory(MemoryPolicy::Error, Protection::Off);
+        let past = Address(MEM_BYTES as u16);
+        assert_eq!(ram.read(past), Err(EmulationError::MemoryOutOfBounds(past)));
+        assert_eq!(
+            ram.write(past, 0xAB),
+            Err(EmulationError::MemoryOutOfBounds(past))
+        );
+        assert_eq!(ram.offset(Address(0xFFF), 1), Ok(past));
+        assert_eq!(
+            ram.offset(Address(0xFFFF), 1),
+            Err(EmulationError::MemoryOutOfBounds(Address(0xFFFF)))
+        );
+    }
+
+    #[test]
+    fn writes_below_the_program_follow_the_protection() {
+        let ram = memory(MemoryPolicy::Error, Protection::Error);
+        assert_eq!(
+            ram.check_write(Address(0x010)),
+            Err(EmulationError::ProtectedWrite(Address(0x010), "font"))
+        );
+        assert_eq!(
+            ram.check_write(Address(0x1FF)),
+            Err(EmulationError::ProtectedWrite(
+                Address(0x1FF),
+                "interpreter"
+            ))
+        );
+        assert_eq!(ram.check_write(Address(0x200)), Ok(0x200));
+
+        // Warning and turning protection off both let the write through
+        for protection in [Protection::Warn, Protection::Off] {
+            let ram = memory(MemoryPolicy::Error, protection);
+            for addr in [0x000, 0x010, 0x1FF] {
+                assert_eq!(ram.check_write(Address(addr)), Ok(addr as usize));
+            }
         }
     }
+
+    #[test]
+    fn load_rom_bytes_rejects_roms_that_cant_load() {
+        let mut ram = RAM::new(MemoryConfig::default());
+        assert_eq!(
+            ram.load_rom_bytes(&[], 0x200).unwrap_err(),
+            "The ROM is empty"
+        );
+        assert_eq!(
+            ram.load_rom_bytes(&[0; 3585], 0x200).unwrap_err(),
+            "The ROM is 3585 bytes, but only 3584 fit in program memory at 0x200"
+        );
+        assert_eq!(
+            ram.load_rom_bytes(&[0x00, 0xE0], 0x100).unwrap_err(),
+            "Load address 0x100 is outside program memory"
+        );
+        assert_eq!(
+            ram.load_rom_bytes(&[0x00, 0xE0], 0x1000).unwrap_err(),
+            "Load address 0x1000 is outside program memory"
+        );
+
+        // The largest ROM that fits, which leaves memory as it was when it's rejected
+        let info = ram.load_rom_bytes(&[0xAA; 3584], 0x200).unwrap();
+        assert_eq!(info.size, 3584);
+        assert!(ram.load_rom_bytes(&[0xBB; 3585], 0x200).is_err());
+        assert_eq!(ram.bytes()[0x200], 0xAA);
+    }
 }
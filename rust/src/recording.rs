@@ -0,0 +1,163 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::filter::Image;
+use crate::screenshot;
+
+/// What a recording is saved as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// An animated GIF.
+    Gif,
+    /// A directory of numbered PNGs, one for every frame.
+    Png,
+}
+
+/// Records the display's pictures, one for each 60Hz frame.
+pub struct Recorder {
+    path: PathBuf,
+    target: Target,
+    frames: usize,
+}
+
+enum Target {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        // The last picture, which isn't written until it's known how long it shows for, and
+        // the frame it first showed in
+        pending: Option<(Image, usize)>,
+    },
+    Png,
+}
+
+impl Recorder {
+    /// Start recording to a GIF file or a PNG directory, of pictures of the given size.
+    pub fn create(
+        path: &Path,
+        format: Format,
+        width: usize,
+        height: usize,
+    ) -> io::Result<Recorder> {
+        let target = match format {
+            Format::Gif => {
+                let file = BufWriter::new(File::create(path)?);
+                let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[])
+                    .map_err(io::Error::other)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(io::Error::other)?;
+                Target::Gif {
+                    encoder,
+                    pending: None,
+                }
+            }
+            Format::Png => {
+                fs::create_dir_all(path)?;
+                Target::Png
+            }
+        };
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            target,
+            frames: 0,
+        })
+    }
+
+    /// Start recording in the current directory, named after the time it started.
+    pub fn start(format: Format, width: usize, height: usize) -> io::Result<Recorder> {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let name = match format {
+            Format::Gif => format!("chip-8-recording-{}.gif", stamp),
+            Format::Png => format!("chip-8-recording-{}", stamp),
+        };
+        Recorder::create(Path::new(&name), format, width, height)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add the picture for the next frame.
+    pub fn add_frame(&mut self, image: &Image) -> io::Result<()> {
+        let frame = self.frames;
+        self.frames += 1;
+        match &mut self.target {
+            Target::Gif { encoder, pending } => {
+                // A GIF's frames last whole hundredths of a second, and players slow down any
+                // shorter than two, so a picture is only written once it's shown that long.
+                // Frames that don't change anything just make the last picture last longer
+                if let Some((last, start)) = pending {
+                    if *last == *image || centiseconds(frame) - centiseconds(*start) < 2 {
+                        return Ok(());
+                    }
+                    let delay = centiseconds(frame) - centiseconds(*start);
+                    write_gif_frame(encoder, last, delay)?;
+                }
+                *pending = Some((image.clone(), frame));
+                Ok(())
+            }
+            Target::Png => {
+                screenshot::write_png(&self.path.join(format!("{:05}.png", frame)), image)
+            }
+        }
+    }
+
+    /// Write out the rest of the recording, returning how many frames it has.
+    pub fn finish(mut self) -> io::Result<usize> {
+        if let Target::Gif { encoder, pending } = &mut self.target {
+            if let Some((last, start)) = pending.take() {
+                let delay = (centiseconds(self.frames) - centiseconds(start)).max(2);
+                write_gif_frame(encoder, &last, delay)?;
+            }
+        }
+        Ok(self.frames)
+    }
+}
+
+// When a 60Hz frame starts, to the nearest hundredth of a second
+fn centiseconds(frame: usize) -> usize {
+    (frame * 100 + 30) / 60
+}
+
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<BufWriter<File>>,
+    image: &Image,
+    delay: usize,
+) -> io::Result<()> {
+    let mut bytes = image.bytes();
+    let mut frame =
+        gif::Frame::from_rgba_speed(image.width as u16, image.height as u16, &mut bytes, 10);
+    frame.delay = delay.min(u16::MAX as usize) as u16;
+    encoder.write_frame(&frame).map_err(io::Error::other)
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "gif" => Ok(Format::Gif),
+            "png" => Ok(Format::Png),
+            _ => Err(format!(
+                "Unknown recording format `{}` (expected gif or png)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Gif => write!(f, "gif"),
+            Format::Png => write!(f, "png"),
+        }
+    }
+}
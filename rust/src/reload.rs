@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::archive;
+use crate::cartridge;
+use crate::machine::Machine;
+use crate::rom::RomInfo;
+use crate::snapshot;
+
+// How often a watched ROM file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The ROM the machine was started with and where it came from, so it can be reset to the
+/// start of it, or load it again once it's been changed.
+pub struct Reloader {
+    // None when the ROM was read from standard input
+    path: Option<PathBuf>,
+    entry: Option<String>,
+    addr: u16,
+    // The ROM as it was last loaded
+    rom: Vec<u8>,
+    watch: Option<Watch>,
+}
+
+struct Watch {
+    // Keep the machine's state across a reload, only replacing the ROM
+    keep_state: bool,
+    last_check: Instant,
+    // The file's modification time when it was last loaded, and when it was last checked. A
+    // change is only loaded once the time has stayed the same for a check, so that a ROM
+    // still being written isn't loaded half-way through
+    loaded: Option<SystemTime>,
+    seen: Option<SystemTime>,
+}
+
+impl Reloader {
+    pub fn new(path: Option<PathBuf>, entry: Option<String>, addr: u16, rom: Vec<u8>) -> Reloader {
+        Reloader {
+            path,
+            entry,
+            addr,
+            rom,
+            watch: None,
+        }
+    }
+
+    /// Look out for the ROM file changing, for `reload_if_changed`.
+    pub fn watch(&mut self, keep_state: bool) {
+        let modified = self.modified();
+        self.watch = Some(Watch {
+            keep_state,
+            last_check: Instant::now(),
+            loaded: modified,
+            seen: modified,
+        });
+    }
+
+    /// Soft reset: start the machine afresh with the ROM as it was last loaded.
+    pub fn reset(&self, machine: &mut Machine) -> Result<RomInfo, String> {
+        machine.reset(&self.rom, self.addr)
+    }
+
+    /// Hard reset: read the ROM file again and start the machine afresh with it. A ROM read
+    /// from standard input can't be read again, so it starts with the same one.
+    pub fn reload(&mut self, machine: &mut Machine) -> Result<RomInfo, String> {
+        let rom = self.read()?;
+        let info = machine.reset(&rom, self.addr)?;
+        self.rom = rom;
+        Ok(info)
+    }
+
+    /// Load the ROM file again if it's changed since it was last loaded, returning what was
+    /// loaded. With the state kept, the machine carries on where it was with the new ROM
+    /// written over the old one; otherwise it starts afresh.
+    pub fn reload_if_changed(&mut self, machine: &mut Machine) -> Option<Result<RomInfo, String>> {
+        if self.watch.as_ref()?.last_check.elapsed() < WATCH_INTERVAL {
+            return None;
+        }
+        let modified = self.modified();
+        let watch = self.watch.as_mut()?;
+        watch.last_check = Instant::now();
+        if modified != watch.seen {
+            watch.seen = modified;
+            return None;
+        }
+        if modified == watch.loaded {
+            return None;
+        }
+        watch.loaded = modified;
+
+        if !watch.keep_state {
+            return Some(self.reload(machine));
+        }
+        let mut state = Vec::new();
+        snapshot::save(machine, &mut state).expect("writing to a Vec can't fail");
+        let result = self.reload(machine).and_then(|info| {
+            snapshot::load(machine, &mut state.as_slice())
+                .expect("a snapshot of this machine fits it");
+            machine.ram.load_rom_bytes(&self.rom, self.addr)?;
+            Ok(info)
+        });
+        Some(result)
+    }
+
+    fn read(&self) -> Result<Vec<u8>, String> {
+        let Some(path) = &self.path else {
+            return Ok(self.rom.clone());
+        };
+        let rom = if cartridge::is_cartridge(path) {
+            cartridge::load(path).map(|cart| cart.program.rom)
+        } else {
+            archive::read_rom_file(path, self.entry.as_deref())
+        };
+        rom.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let path = self.path.as_ref()?;
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
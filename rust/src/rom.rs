@@ -0,0 +1,30 @@
+use std::fmt;
+
+/// What was loaded, for logging and for looking the ROM up by its hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomInfo {
+    pub size: usize,
+    pub load_address: u16,
+    // Lowercase hex, as ROM databases list them
+    pub sha1: String,
+}
+
+impl RomInfo {
+    pub fn new(rom: &[u8], load_address: u16) -> RomInfo {
+        RomInfo {
+            size: rom.len(),
+            load_address,
+            sha1: sha1_smol::Sha1::from(rom).digest().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes at {:#05X}, SHA-1 {}",
+            self.size, self.load_address, self.sha1
+        )
+    }
+}
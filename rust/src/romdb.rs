@@ -0,0 +1,101 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::palette::{self, Palette};
+use crate::quirks::{Platform, Quirks};
+
+// Known ROMs, keyed by their SHA-1. Each entry has a title and platform, and optionally an
+// author, quirks that differ from the platform's, a tickrate (instructions per frame), what the
+// keys do and colors ("background" and "fill", and for XO-CHIP optionally "fill2" and "blend")
+const DATABASE: &str = include_str!("../data/roms.json");
+
+/// What the ROM database knows about a ROM, and the settings it runs best with.
+pub struct RomEntry {
+    pub title: String,
+    pub author: Option<String>,
+    pub platform: Platform,
+    // The platform's quirks with the ROM's own exceptions applied
+    pub quirks: Quirks,
+    pub tickrate: Option<usize>,
+    // Hex key and what it does, in key order
+    pub keys: Vec<(u8, String)>,
+    pub palette: Option<Palette>,
+}
+
+/// Look a ROM up by its SHA-1, as given by `RomInfo`.
+pub fn lookup(sha1: &str) -> Result<Option<RomEntry>, String> {
+    let database: Value = serde_json::from_str(DATABASE).map_err(|e| e.to_string())?;
+    match database.get(sha1) {
+        Some(entry) => parse_entry(entry)
+            .map(Some)
+            .map_err(|e| format!("Bad ROM database entry for {}: {}", sha1, e)),
+        None => Ok(None),
+    }
+}
+
+fn parse_entry(entry: &Value) -> Result<RomEntry, String> {
+    let string = |name: &str| entry[name].as_str().map(str::to_string);
+    let title = string("title").ok_or("no title")?;
+    let platform: Platform = entry["platform"].as_str().ok_or("no platform")?.parse()?;
+
+    let mut quirks = platform.quirks();
+    if let Some(overrides) = entry["quirks"].as_object() {
+        for (name, on) in overrides {
+            quirks.set(name, on.as_bool().ok_or("quirks must be true or false")?)?;
+        }
+    }
+
+    let mut keys = Vec::new();
+    if let Some(hints) = entry["keys"].as_object() {
+        for (key, hint) in hints {
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&k| k < 16)
+                .ok_or(format!("`{}` is not a key", key))?;
+            keys.push((
+                key,
+                hint.as_str().ok_or("key hints must be text")?.to_string(),
+            ));
+        }
+    }
+    keys.sort();
+
+    let palette = match &entry["colors"] {
+        Value::Null => None,
+        colors => {
+            let color = |name: &str| -> Result<Option<palette::Rgb>, String> {
+                colors[name].as_str().map(palette::parse_color).transpose()
+            };
+            let missing = "colors need a background and a fill";
+            let default = Palette::default();
+            Some(Palette {
+                background: color("background")?.ok_or(missing)?,
+                fill: color("fill")?.ok_or(missing)?,
+                fill2: color("fill2")?.unwrap_or(default.fill2),
+                blend: color("blend")?.unwrap_or(default.blend),
+            })
+        }
+    };
+
+    Ok(RomEntry {
+        title,
+        author: string("author"),
+        platform,
+        quirks,
+        tickrate: entry["tickrate"].as_u64().map(|n| n as usize),
+        keys,
+        palette,
+    })
+}
+
+// Like `Space Invaders by David Winter (CHIP-8)`
+impl fmt::Display for RomEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.title)?;
+        if let Some(author) = &self.author {
+            write!(f, " by {}", author)?;
+        }
+        write!(f, " ({})", self.platform)
+    }
+}
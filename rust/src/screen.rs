@@ -0,0 +1,70 @@
+use crate::config::Settings;
+use crate::delta::Delta;
+use crate::error::EmulationError;
+use crate::filter::{Filter, Image};
+use crate::machine::Machine;
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+use crate::present::Presenter;
+
+/// Everything between the VRAM and a picture: the presentation mode, fading pixels, the
+/// palette, the filter and the scale. The window, screenshots and recordings all show its
+/// pictures, so they look the same.
+pub struct Screen {
+    presenter: Presenter,
+    phosphor: Phosphor,
+    palette: Palette,
+    filter: Filter,
+    scale: usize,
+}
+
+impl Screen {
+    pub fn new(settings: &Settings) -> Screen {
+        Screen {
+            presenter: Presenter::new(settings.present),
+            phosphor: Phosphor::new(settings.decay),
+            palette: settings.palette,
+            filter: settings.filter,
+            scale: settings.scale as usize,
+        }
+    }
+
+    /// Run one instruction, letting the presentation mode see what it does.
+    pub fn step(&mut self, machine: &mut Machine) -> Result<Delta, EmulationError> {
+        self.presenter.before_step(machine);
+        let delta = machine.step()?;
+        self.presenter.after_step(&delta, &machine.vram);
+        Ok(delta)
+    }
+
+    /// Finish a 60Hz frame: count the timers down and make the frame's picture.
+    pub fn end_frame(&mut self, machine: &mut Machine) {
+        machine.cpu.tick_timers();
+        self.phosphor
+            .update(self.presenter.end_frame(&machine.vram));
+    }
+
+    /// The filtered picture, at the size the display is drawn at before it's scaled.
+    pub fn filtered(&self) -> Image {
+        self.filter
+            .apply(&Image::render(self.phosphor.levels(), &self.palette))
+    }
+
+    /// The picture as screenshots and recordings save it, at the window's size.
+    pub fn image(&self) -> Image {
+        self.filtered().scale(self.scale)
+    }
+
+    /// The size of `filtered`'s pictures.
+    pub fn filtered_size(&self) -> (u32, u32) {
+        let factor = self.filter.factor() as u32;
+        (
+            crate::DISPLAY_WIDTH as u32 * factor,
+            crate::DISPLAY_HEIGHT as u32 * factor,
+        )
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale as u32
+    }
+}
@@ -0,0 +1,28 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::filter::Image;
+
+/// Save a picture of the display in the current directory, named after the time it was taken.
+pub fn save(image: &Image) -> io::Result<PathBuf> {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let path = PathBuf::from(format!("chip-8-screenshot-{}.png", stamp));
+    write_png(&path, image)?;
+    Ok(path)
+}
+
+pub fn write_png(path: &Path, image: &Image) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer
+        .write_image_data(&image.bytes())
+        .map_err(io::Error::other)
+}
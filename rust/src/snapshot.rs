@@ -0,0 +1,100 @@
+use std::io::{self, Read, Write};
+
+use crate::machine::Machine;
+use crate::ram::MEM_BYTES;
+use crate::stack::Stack;
+use crate::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+// Snapshots start with these bytes, followed by a format version
+const MAGIC: &[u8; 8] = b"CHIP8SS\0";
+const VERSION: u8 = 1;
+
+/// Write the whole machine state (registers, timers, stack, VRAM and RAM) so it can be restored
+/// with `load` later on.
+pub fn save(machine: &Machine, out: &mut impl Write) -> io::Result<()> {
+    let cpu = &machine.cpu;
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    out.write_all(&cpu.program_counter.to_be_bytes())?;
+    out.write_all(&cpu.index_register.to_be_bytes())?;
+    out.write_all(&cpu.general_registers)?;
+    out.write_all(&[cpu.delay_timer, cpu.sound_timer])?;
+
+    let stack: Vec<u16> = machine.stack.iter().copied().collect();
+    out.write_all(&[stack.len() as u8])?;
+    for addr in stack {
+        out.write_all(&addr.to_be_bytes())?;
+    }
+
+    for row in machine.vram.iter() {
+        out.write_all(row)?;
+    }
+    out.write_all(machine.ram.bytes())
+}
+
+/// Replace the machine state with a snapshot written by `save`. The machine is only touched
+/// once the whole snapshot has been read successfully.
+pub fn load(machine: &mut Machine, input: &mut impl Read) -> io::Result<()> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a CHIP-8 snapshot"));
+    }
+    if read_u8(input)? != VERSION {
+        return Err(invalid("unsupported snapshot version"));
+    }
+
+    let program_counter = read_u16(input)?;
+    let index_register = read_u16(input)?;
+    let mut general_registers = [0; 16];
+    input.read_exact(&mut general_registers)?;
+    let delay_timer = read_u8(input)?;
+    let sound_timer = read_u8(input)?;
+
+    let mut stack = Stack::new(machine.stack.config());
+    let depth = read_u8(input)? as usize;
+    if depth > stack.capacity() {
+        return Err(invalid("stack is deeper than this machine's"));
+    }
+    for _ in 0..depth {
+        stack
+            .push(read_u16(input)?)
+            .map_err(|_| invalid("stack overflow"))?;
+    }
+
+    let mut vram = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+    for row in vram.iter_mut() {
+        input.read_exact(row)?;
+    }
+    let mut memory = vec![0; MEM_BYTES];
+    input.read_exact(&mut memory)?;
+
+    machine.cpu.program_counter = program_counter;
+    machine.cpu.index_register = index_register;
+    machine.cpu.general_registers = general_registers;
+    machine.cpu.delay_timer = delay_timer;
+    machine.cpu.sound_timer = sound_timer;
+    machine.stack = stack;
+    machine.vram = vram;
+    machine
+        .ram
+        .load(0, &memory)
+        .expect("a snapshot's memory is exactly the size of RAM");
+    Ok(())
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(input: &mut impl Read) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    input.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -1,34 +1,200 @@
-use log::warn;
+use std::str::FromStr;
 
-const STACK_SIZE: usize = 16;
+use crate::error::EmulationError;
+use crate::ram::Address;
+
+/// The deepest stack of any supported interpreter (SCHIP's).
+pub const MAX_STACK_DEPTH: usize = 16;
+
+// On the VIP the stack sits just below the interpreter's variables and grows down from here
+const VIP_STACK_END: u16 = 0xED0;
+
+/// What to do when a CALL finds the stack full, or a RET finds it empty.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackPolicy {
+    /// Stop emulation with an error.
+    Error,
+    /// Wrap the stack pointer around, overwriting the oldest entry or returning to the newest.
+    Wrap,
+    /// Drop the push (the CALL still jumps), or treat the RET as a no-op.
+    Ignore,
+}
+
+impl FromStr for StackPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StackPolicy, String> {
+        match s {
+            "error" => Ok(StackPolicy::Error),
+            "wrap" => Ok(StackPolicy::Wrap),
+            "ignore" => Ok(StackPolicy::Ignore),
+            _ => Err(format!(
+                "Unknown stack policy `{}` (expected error, wrap or ignore)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StackConfig {
+    pub depth: usize,
+    pub policy: StackPolicy,
+    // Mirror the stack into emulated RAM where the VIP keeps it, for ROMs that peek at it
+    pub in_ram: bool,
+}
+
+impl Default for StackConfig {
+    fn default() -> StackConfig {
+        StackConfig {
+            depth: MAX_STACK_DEPTH,
+            policy: StackPolicy::Error,
+            in_ram: false,
+        }
+    }
+}
 
 pub struct Stack {
-    stack_pointer: u16,
-    s: Vec<u16>,
+    entries: [u16; MAX_STACK_DEPTH],
+    stack_pointer: usize,
+    config: StackConfig,
 }
 
 impl Stack {
-    pub fn new() -> Stack {
+    /// An empty stack. `config.depth` must be between 1 and `MAX_STACK_DEPTH`.
+    pub fn new(config: StackConfig) -> Stack {
+        assert!((1..=MAX_STACK_DEPTH).contains(&config.depth));
         Stack {
+            entries: [0; MAX_STACK_DEPTH],
             stack_pointer: 0,
-            s: Vec::with_capacity(STACK_SIZE),
+            config,
         }
     }
 
-    pub fn push(&mut self, v: u16) {
-        self.s.push(v);
+    pub fn config(&self) -> StackConfig {
+        self.config
+    }
+
+    pub fn stack_pointer(&self) -> u16 {
+        self.stack_pointer as u16
+    }
+
+    /// How many return addresses are on the stack.
+    pub fn depth(&self) -> usize {
+        self.stack_pointer
+    }
+
+    /// How many return addresses the stack can hold.
+    pub fn capacity(&self) -> usize {
+        self.config.depth
+    }
+
+    /// Push a return address, returning the slot it went into and the value it replaced, or
+    /// `None` if the stack was full and the push was ignored.
+    pub fn push(&mut self, v: u16) -> Result<Option<(usize, u16)>, EmulationError> {
+        if self.stack_pointer == self.config.depth {
+            match self.config.policy {
+                StackPolicy::Error => return Err(EmulationError::StackOverflow),
+                StackPolicy::Wrap => self.stack_pointer = 0,
+                StackPolicy::Ignore => return Ok(None),
+            }
+        }
+        let slot = self.stack_pointer;
+        let old = self.entries[slot];
+        self.entries[slot] = v;
         self.stack_pointer += 1;
+        Ok(Some((slot, old)))
     }
 
-    pub fn pop(&mut self) -> u16 {
-        let v = match self.s.pop() {
-            Some(x) => x,
-            None => {
-                warn!("The stack just tried to pop an empty list");
-                0
+    /// Pop a return address, returning it along with the slot it came from, or `None` if the
+    /// stack was empty and the pop was ignored.
+    pub fn pop(&mut self) -> Result<Option<(usize, u16)>, EmulationError> {
+        if self.stack_pointer == 0 {
+            match self.config.policy {
+                StackPolicy::Error => return Err(EmulationError::StackUnderflow),
+                StackPolicy::Wrap => self.stack_pointer = self.config.depth,
+                StackPolicy::Ignore => return Ok(None),
             }
-        };
+        }
         self.stack_pointer -= 1;
-        v
+        let slot = self.stack_pointer;
+        Ok(Some((slot, self.entries[slot])))
+    }
+
+    /// Where a slot lives in emulated RAM, if the stack is kept there.
+    pub fn ram_address(&self, slot: usize) -> Option<Address> {
+        let offset = 2 * (slot as u16 + 1);
+        self.config.in_ram.then(|| Address(VIP_STACK_END - offset))
+    }
+
+    /// Put the stack pointer back to an earlier value, to undo an instruction.
+    pub fn restore_pointer(&mut self, stack_pointer: usize) {
+        self.stack_pointer = stack_pointer;
+    }
+
+    /// Put a slot back to an earlier value, to undo an instruction.
+    pub fn restore_entry(&mut self, slot: usize, v: u16) {
+        self.entries[slot] = v;
+    }
+
+    /// The return addresses currently on the stack, oldest first.
+    pub fn iter(&self) -> std::slice::Iter<'_, u16> {
+        self.entries[..self.stack_pointer].iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stack of the full depth with every slot pushed
+    fn full(policy: StackPolicy) -> Stack {
+        let mut stack = Stack::new(StackConfig {
+            policy,
+            ..StackConfig::default()
+        });
+        for n in 0..MAX_STACK_DEPTH as u16 {
+            assert_eq!(stack.push(0x200 + 2 * n).unwrap(), Some((n as usize, 0)));
+        }
+        stack
+    }
+
+    #[test]
+    fn errors_past_either_end() {
+        let mut stack = full(StackPolicy::Error);
+        assert_eq!(stack.push(0x300), Err(EmulationError::StackOverflow));
+        assert_eq!(stack.depth(), MAX_STACK_DEPTH);
+
+        for _ in 0..MAX_STACK_DEPTH {
+            stack.pop().unwrap();
+        }
+        assert_eq!(stack.pop(), Err(EmulationError::StackUnderflow));
+        assert_eq!(stack.depth(), 0);
+    }
+
+    #[test]
+    fn wraps_past_either_end() {
+        let mut stack = full(StackPolicy::Wrap);
+        // The oldest entry is overwritten
+        assert_eq!(stack.push(0x300).unwrap(), Some((0, 0x200)));
+        assert_eq!(stack.depth(), 1);
+
+        assert_eq!(stack.pop().unwrap(), Some((0, 0x300)));
+        // Then popping an empty stack goes back round to the newest
+        assert_eq!(stack.pop().unwrap(), Some((15, 0x21E)));
+        assert_eq!(stack.depth(), 15);
+    }
+
+    #[test]
+    fn ignores_pushes_when_full_and_pops_when_empty() {
+        let mut stack = full(StackPolicy::Ignore);
+        assert_eq!(stack.push(0x300).unwrap(), None);
+        assert_eq!(stack.iter().last(), Some(&0x21E));
+
+        for _ in 0..MAX_STACK_DEPTH {
+            stack.pop().unwrap();
+        }
+        assert_eq!(stack.pop().unwrap(), None);
+        assert_eq!(stack.depth(), 0);
     }
 }
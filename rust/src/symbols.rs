@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+// Addresses further than this past the nearest label aren't worth naming after it
+const MAX_LABEL_OFFSET: u16 = 0x100;
+
+/// Something a symbol file says about an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Entry {
+    Label(String),
+    Breakpoint(String),
+    Source { file: String, line: usize },
+}
+
+/// Names and source positions for a ROM's addresses, written alongside it by the assembler and
+/// the Octo compiler.
+///
+/// The file format is one entry per line, with the address in hex:
+/// ```text
+/// 202 label main
+/// 20A break check_input
+/// 20A line game.8o:14
+/// ```
+/// A line with just an address and a name is a label too, so a hand-written map can be as
+/// simple as `2A6 draw_player`. Blank lines and lines starting with `#` are ignored.
+#[derive(Default)]
+pub struct Symbols {
+    // An address can have several labels; the first one defined is the one displayed
+    labels: BTreeMap<u16, Vec<String>>,
+    breakpoints: BTreeMap<u16, String>,
+    lines: BTreeMap<u16, (String, usize)>,
+}
+
+impl Symbols {
+    pub fn add(&mut self, addr: u16, entry: Entry) {
+        match entry {
+            Entry::Label(name) => self.labels.entry(addr).or_default().push(name),
+            Entry::Breakpoint(name) => {
+                self.breakpoints.insert(addr, name);
+            }
+            Entry::Source { file, line } => {
+                self.lines.entry(addr).or_insert((file, line));
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let name = path.display();
+        let file = File::open(path).map_err(|e| format!("Could not read {}: {}", name, e))?;
+        Symbols::read(BufReader::new(file)).map_err(|e| format!("{}:{}", name, e))
+    }
+
+    /// Parse a symbol file. Errors start with the line number they were found on.
+    pub fn read(input: impl BufRead) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for (n, line) in input.lines().enumerate() {
+            let line = line.map_err(|e| format!("{}: {}", n + 1, e))?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            // A source file's path can have spaces in it, so it runs to the end of the line
+            let fields: Vec<&str> = line.trim().splitn(3, char::is_whitespace).collect();
+            let invalid = || format!("{}: Invalid symbol entry `{}`", n + 1, line.trim());
+
+            let (addr, entry) = match (words.as_slice(), fields.as_slice()) {
+                ([], _) => continue,
+                ([first, ..], _) if first.starts_with('#') => continue,
+                (_, [addr, "line", position]) => {
+                    let (file, line) = position.trim().rsplit_once(':').ok_or_else(invalid)?;
+                    let line = line.parse().map_err(|_| invalid())?;
+                    let file = file.to_string();
+                    (addr, Entry::Source { file, line })
+                }
+                ([addr, name], _) => (addr, Entry::Label(name.to_string())),
+                ([addr, "label", name], _) => (addr, Entry::Label(name.to_string())),
+                ([addr, "break", name], _) => (addr, Entry::Breakpoint(name.to_string())),
+                _ => return Err(invalid()),
+            };
+            let digits = addr.trim_start_matches("0x").trim_start_matches("0X");
+            let addr = u16::from_str_radix(digits, 16).map_err(|_| invalid())?;
+            symbols.add(addr, entry);
+        }
+        Ok(symbols)
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut entries: Vec<(u16, Entry)> = Vec::new();
+        for (&addr, names) in &self.labels {
+            entries.extend(names.iter().map(|n| (addr, Entry::Label(n.clone()))));
+        }
+        for (&addr, name) in &self.breakpoints {
+            entries.push((addr, Entry::Breakpoint(name.clone())));
+        }
+        for (&addr, (file, line)) in &self.lines {
+            let (file, line) = (file.clone(), *line);
+            entries.push((addr, Entry::Source { file, line }));
+        }
+        // A stable sort keeps labels before breakpoints before lines at each address
+        entries.sort_by_key(|(addr, _)| *addr);
+        for (addr, entry) in entries {
+            writeln!(out, "{:03X} {}", addr, entry)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.breakpoints.is_empty() && self.lines.is_empty()
+    }
+
+    /// Name `addr` after the nearest label at or before it, like `draw_player+0x4`.
+    pub fn symbolize(&self, addr: u16) -> Option<String> {
+        let (&base, names) = self.labels.range(..=addr).next_back()?;
+        match addr - base {
+            0 => Some(names[0].clone()),
+            offset if offset < MAX_LABEL_OFFSET => Some(format!("{}+{:#X}", names[0], offset)),
+            _ => None,
+        }
+    }
+
+    /// Format an address as hex, followed by its symbolic name if it has one.
+    pub fn describe(&self, addr: u16) -> String {
+        match self.symbolize(addr) {
+            Some(name) => format!("{:#05X} <{}>", addr, name),
+            None => format!("{:#05X}", addr),
+        }
+    }
+
+    /// The source line that the instruction at `addr` was compiled from.
+    pub fn source(&self, addr: u16) -> Option<(&str, usize)> {
+        self.lines
+            .get(&addr)
+            .map(|(file, line)| (file.as_str(), *line))
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, names)| names.iter().any(|n| n == name))
+            .map(|(&addr, _)| addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = (u16, &str)> + '_ {
+        self.labels
+            .iter()
+            .map(|(&addr, names)| (addr, names[0].as_str()))
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Entry::Label(name) => write!(f, "label {}", name),
+            Entry::Breakpoint(name) => write!(f, "break {}", name),
+            Entry::Source { file, line } => write!(f, "line {}:{}", file, line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_paths_with_spaces_read_back() {
+        let mut symbols = Symbols::default();
+        symbols.add(0x200, Entry::Label("main".to_string()));
+        let file = "/tmp/my dir/x.asm".to_string();
+        symbols.add(0x202, Entry::Source { file, line: 2 });
+        let mut out = Vec::new();
+        symbols.write(&mut out).unwrap();
+
+        let symbols = Symbols::read(out.as_slice()).unwrap();
+        assert_eq!(symbols.address_of("main"), Some(0x200));
+        assert_eq!(symbols.source(0x202), Some(("/tmp/my dir/x.asm", 2)));
+    }
+
+    #[test]
+    fn bad_entries_give_their_line() {
+        let input = "200 main\n\n# a comment\n202 line x.asm\n";
+        assert_eq!(
+            Symbols::read(input.as_bytes()).err().unwrap(),
+            "4: Invalid symbol entry `202 line x.asm`"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+use crate::cpu::CPU;
+use crate::instruction::Instruction;
+use crate::ram::{Address, RAM};
+use crate::stack::Stack;
+use crate::symbols::Symbols;
+
+// Wide enough for the longest mnemonic, so the columns after it line up
+const MNEMONIC_WIDTH: usize = 16;
+// Wide enough for most `label+0xNN` names
+const SYMBOL_WIDTH: usize = 24;
+
+/// A field of a trace line. Columns are always written in the order declared here, whatever
+/// order they were selected in, so two traces with the same columns can be diffed line by line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Column {
+    Pc,
+    Symbol,
+    Opcode,
+    Mnemonic,
+    Registers,
+    Index,
+    StackPointer,
+    Timers,
+}
+
+pub const ALL_COLUMNS: [Column; 8] = [
+    Column::Pc,
+    Column::Symbol,
+    Column::Opcode,
+    Column::Mnemonic,
+    Column::Registers,
+    Column::Index,
+    Column::StackPointer,
+    Column::Timers,
+];
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Column, String> {
+        match s {
+            "pc" => Ok(Column::Pc),
+            "sym" => Ok(Column::Symbol),
+            "opcode" => Ok(Column::Opcode),
+            "mnemonic" => Ok(Column::Mnemonic),
+            "regs" => Ok(Column::Registers),
+            "i" => Ok(Column::Index),
+            "sp" => Ok(Column::StackPointer),
+            "timers" => Ok(Column::Timers),
+            _ => Err(format!(
+                "Unknown trace column `{}` (expected pc, sym, opcode, mnemonic, regs, i, sp or timers)",
+                s
+            )),
+        }
+    }
+}
+
+/// How a trace should be written, as given on the command line.
+pub struct TraceConfig {
+    pub path: String,
+    pub columns: Vec<Column>,
+    // Tracing switches on when the PC reaches `start` and off again after it reaches `stop`
+    pub start: Option<u16>,
+    pub stop: Option<u16>,
+}
+
+/// The machine state just before an instruction runs: everything a trace line can show.
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub index: u16,
+    pub stack_pointer: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceEntry {
+    pub fn capture(cpu: &CPU, stack: &Stack, ram: &RAM) -> TraceEntry {
+        TraceEntry {
+            pc: cpu.program_counter,
+            // A PC outside memory faults when it's fetched; until then it reads as 0000
+            opcode: ram.read_word(Address(cpu.program_counter)).unwrap_or(0),
+            registers: cpu.general_registers,
+            index: cpu.index_register,
+            stack_pointer: stack.stack_pointer(),
+            delay_timer: cpu.delay_timer,
+            sound_timer: cpu.sound_timer,
+        }
+    }
+
+    /// Format as a trace line. `columns` must be sorted and free of duplicates. The symbol
+    /// column is left out altogether when there are no symbols loaded.
+    pub fn format(&self, columns: &[Column], symbols: &Symbols) -> String {
+        let fields: Vec<String> = columns
+            .iter()
+            .filter(|&&column| column != Column::Symbol || !symbols.is_empty())
+            .map(|column| match column {
+                Column::Pc => format!("PC={:04X}", self.pc),
+                Column::Symbol => {
+                    let name = symbols.symbolize(self.pc).unwrap_or_default();
+                    format!("{:<width$}", name, width = SYMBOL_WIDTH)
+                }
+                Column::Opcode => format!("OP={:04X}", self.opcode),
+                Column::Mnemonic => {
+                    let mnemonic = match Instruction::decode(self.opcode) {
+                        Some(instruction) => instruction.to_string(),
+                        None => "???".to_string(),
+                    };
+                    format!("{:<width$}", mnemonic, width = MNEMONIC_WIDTH)
+                }
+                Column::Registers => {
+                    let regs: Vec<String> = self
+                        .registers
+                        .iter()
+                        .map(|v| format!("{:02X}", v))
+                        .collect();
+                    format!("V={}", regs.join(" "))
+                }
+                Column::Index => format!("I={:04X}", self.index),
+                Column::StackPointer => format!("SP={:02X}", self.stack_pointer),
+                Column::Timers => {
+                    format!("DT={:02X} ST={:02X}", self.delay_timer, self.sound_timer)
+                }
+            })
+            .collect();
+        fields.join(" ").trim_end().to_string()
+    }
+}
+
+/// Writes one line per executed instruction, describing the machine just before it runs.
+pub struct Tracer {
+    out: BufWriter<File>,
+    columns: Vec<Column>,
+    start: Option<u16>,
+    stop: Option<u16>,
+    active: bool,
+}
+
+impl Tracer {
+    pub fn create(config: &TraceConfig) -> io::Result<Tracer> {
+        let mut columns = config.columns.clone();
+        columns.sort();
+        columns.dedup();
+
+        Ok(Tracer {
+            out: BufWriter::new(File::create(&config.path)?),
+            columns,
+            start: config.start,
+            stop: config.stop,
+            active: config.start.is_none(),
+        })
+    }
+
+    /// Record an instruction about to execute, if tracing is currently switched on.
+    pub fn trace(&mut self, entry: &TraceEntry, symbols: &Symbols) -> io::Result<()> {
+        if Some(entry.pc) == self.start {
+            self.active = true;
+        }
+        if !self.active {
+            return Ok(());
+        }
+
+        writeln!(self.out, "{}", entry.format(&self.columns, symbols))?;
+
+        if Some(entry.pc) == self.stop {
+            self.active = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::machine::Machine;
+    use crate::ram::PROG_MEM_START;
+
+    #[test]
+    fn traces_from_start_to_stop() {
+        let path = std::env::temp_dir().join(format!("chip-8-trace-{}.txt", std::process::id()));
+        let config = TraceConfig {
+            path: path.to_string_lossy().into_owned(),
+            // Out of order, and twice over, which makes no difference
+            columns: vec![
+                Column::Timers,
+                Column::Pc,
+                Column::Mnemonic,
+                Column::Opcode,
+                Column::Pc,
+            ],
+            start: Some(0x202),
+            stop: Some(0x204),
+        };
+        let mut machine = Machine::new();
+        let rom = [0x60, 0x01, 0x61, 0x02, 0xF1, 0x15, 0x63, 0x04];
+        machine
+            .ram
+            .load_rom_bytes(&rom, PROG_MEM_START as u16)
+            .unwrap();
+        machine.tracer = Some(Tracer::create(&config).unwrap());
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+        // Dropping the tracer flushes it
+        machine.tracer = None;
+
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            trace,
+            "PC=0202 OP=6102 LD V1, 0x02      DT=00 ST=00\n\
+             PC=0204 OP=F115 LD DT, V1        DT=00 ST=00\n"
+        );
+    }
+}
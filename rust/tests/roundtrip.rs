@@ -0,0 +1,64 @@
+//! Disassembling every ROM in roms/ and assembling the listing again gives back the same bytes.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn chip8(args: &[&str]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_chip-8"))
+        .args(args)
+        .output()
+        .expect("the chip-8 binary runs");
+    assert!(
+        output.status.success(),
+        "chip-8 {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+fn roms() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms");
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("roms/ is there")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "ch8"))
+        .collect();
+    roms.sort();
+    roms
+}
+
+#[test]
+fn disassembly_assembles_to_the_same_rom() {
+    let dir = std::env::temp_dir().join(format!("chip-8-roundtrip-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let roms = roms();
+    assert!(!roms.is_empty());
+    for rom in roms {
+        let name = rom.file_stem().unwrap().to_string_lossy().to_string();
+        for mode in [None, Some("--recursive")] {
+            let mut args = vec!["disasm", rom.to_str().unwrap()];
+            args.extend(mode);
+            let listing = chip8(&args);
+
+            let source = dir.join(format!("{}.asm", name));
+            let output = dir.join(format!("{}.ch8", name));
+            fs::write(&source, listing).unwrap();
+            chip8(&[
+                "asm",
+                source.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+            ]);
+            assert_eq!(
+                fs::read(&output).unwrap(),
+                fs::read(&rom).unwrap(),
+                "{} ({})",
+                name,
+                mode.unwrap_or("linear")
+            );
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}